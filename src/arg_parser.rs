@@ -0,0 +1,137 @@
+use anyhow::Error;
+
+pub fn syntax_error() -> Error {
+    Error::msg("ERR syntax error")
+}
+
+pub fn parse_int(arg: &[u8]) -> Result<i64, Error> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|s| s.parse::<i64>().ok())
+        .ok_or(Error::msg("ERR value is not an integer or out of range"))
+}
+
+// A cursor over command arguments, so handlers can take positional values and
+// named options in any order instead of indexing into the argument list.
+pub struct ArgParser<'a> {
+    args: &'a [Vec<u8>],
+    pos: usize,
+}
+
+impl<'a> ArgParser<'a> {
+    pub fn new(args: &'a [Vec<u8>]) -> ArgParser<'a> {
+        ArgParser { args, pos: 0 }
+    }
+
+    pub fn next_string(&mut self) -> Result<&'a [u8], Error> {
+        let arg = self.args.get(self.pos).ok_or_else(syntax_error)?;
+        self.pos += 1;
+        Ok(arg.as_slice())
+    }
+
+    pub fn try_flag(&mut self, flag: &str) -> bool {
+        match self.args.get(self.pos) {
+            Some(arg) if arg.eq_ignore_ascii_case(flag.as_bytes()) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn try_option_with_value(&mut self, name: &str) -> Result<Option<&'a [u8]>, Error> {
+        match self.args.get(self.pos) {
+            Some(arg) if arg.eq_ignore_ascii_case(name.as_bytes()) => {
+                self.pos += 1;
+                self.next_string().map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    pub fn remaining(&self) -> &'a [Vec<u8>] {
+        &self.args[self.pos..]
+    }
+
+    pub fn finish(&self) -> Result<(), Error> {
+        if self.remaining().is_empty() {
+            Ok(())
+        } else {
+            Err(syntax_error())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<Vec<u8>> {
+        args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+    }
+
+    // Takes NX and EX <value> in whichever order they come, like SET does.
+    fn parse_nx_ex(parser: &mut ArgParser) -> Result<(bool, Option<Vec<u8>>), Error> {
+        let (mut nx, mut ex) = (false, None);
+        loop {
+            if parser.try_flag("NX") {
+                nx = true;
+            } else if let Some(value) = parser.try_option_with_value("EX")? {
+                ex = Some(value.to_vec());
+            } else {
+                break;
+            }
+        }
+        parser.finish()?;
+        Ok((nx, ex))
+    }
+
+    #[test]
+    fn options_parse_the_same_in_any_order() {
+        let expected = (true, Some(b"10".to_vec()));
+        for order in [["NX", "EX", "10"], ["EX", "10", "NX"], ["nx", "ex", "10"]] {
+            let args = args(&order);
+            assert_eq!(parse_nx_ex(&mut ArgParser::new(&args)).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn positional_values_come_before_options() {
+        let args = args(&["key", "value", "NX"]);
+        let mut parser = ArgParser::new(&args);
+
+        assert_eq!(parser.next_string().unwrap(), b"key");
+        assert_eq!(parser.next_string().unwrap(), b"value");
+        assert!(!parser.try_flag("XX"));
+        assert_eq!(parser.remaining(), &args[2..]);
+        assert!(parser.try_flag("NX"));
+        assert!(parser.finish().is_ok());
+        assert!(parser.next_string().is_err());
+    }
+
+    #[test]
+    fn leftovers_are_a_syntax_error() {
+        let args = args(&["NX", "EX", "10", "extra"]);
+        let err = parse_nx_ex(&mut ArgParser::new(&args)).unwrap_err();
+        assert_eq!(err.to_string(), "ERR syntax error");
+    }
+
+    #[test]
+    fn option_without_its_value_is_a_syntax_error() {
+        let args = args(&["EX"]);
+        let err = parse_nx_ex(&mut ArgParser::new(&args)).unwrap_err();
+        assert_eq!(err.to_string(), "ERR syntax error");
+    }
+
+    #[test]
+    fn parse_int_rejects_non_integers() {
+        assert_eq!(parse_int(b"-42").unwrap(), -42);
+        for arg in [&b"4.2"[..], b"", b"ten", b"99999999999999999999"] {
+            let err = parse_int(arg).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "ERR value is not an integer or out of range"
+            );
+        }
+    }
+}
@@ -1,69 +1,897 @@
-use std::collections::HashMap;
-use std::time::{Duration, Instant};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Error;
+use rand::seq::IteratorRandom;
+
+pub fn wrong_type_error() -> Error {
+    Error::msg("WRONGTYPE Operation against a key holding the wrong kind of value")
+}
+
+// A deadline too far out to represent as an Instant is treated as no deadline.
+fn deadline_after(mills: u64) -> Option<Instant> {
+    Instant::now().checked_add(Duration::from_millis(mills))
+}
+
+// Redis-style glob matching: `*`, `?`, `[abc]`, `[a-z]`, `[^...]` and `\`
+// to take the next byte literally. Bytes are compared as-is, not as UTF-8.
+//
+// Iterative so that a long pattern can't exhaust the stack. On a mismatch only
+// the most recent star is retried one byte further, an earlier star can never
+// match anything the later one can't, which keeps it at O(pattern * key).
+pub fn glob_match(pattern: &[u8], key: &[u8]) -> bool {
+    let (mut p, mut k) = (0, 0);
+    // Pattern position after the last star and the key position it resumes at.
+    let mut star: Option<(usize, usize)> = None;
+
+    while k < key.len() {
+        let matched = match pattern.get(p) {
+            Some(b'*') => {
+                while pattern.get(p) == Some(&b'*') {
+                    p += 1;
+                }
+                star = Some((p, k));
+                continue;
+            }
+            Some(b'?') => {
+                p += 1;
+                true
+            }
+            Some(b'[') => {
+                let (matched, rest) = match_class(&pattern[p + 1..], key[k]);
+                p = pattern.len() - rest.len();
+                matched
+            }
+            Some(b'\\') if p + 1 < pattern.len() => {
+                p += 2;
+                pattern[p - 1] == key[k]
+            }
+            Some(&literal) => {
+                p += 1;
+                literal == key[k]
+            }
+            None => false,
+        };
+
+        if matched {
+            k += 1;
+        } else if let Some((star_p, star_k)) = star {
+            // Let the last star swallow one more byte and retry from there.
+            p = star_p;
+            k = star_k + 1;
+            star = Some((star_p, k));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+// Matches one byte against the class that starts right after `[`, and
+// returns whether it matched along with the pattern after the closing `]`.
+// An unterminated class runs to the end of the pattern.
+fn match_class(class: &[u8], byte: u8) -> (bool, &[u8]) {
+    let (negate, mut class) = match class.split_first() {
+        Some((b'^', rest)) => (true, rest),
+        _ => (false, class),
+    };
+
+    let mut matched = false;
+    loop {
+        match class {
+            [] => break,
+            [b']', rest @ ..] => {
+                class = rest;
+                break;
+            }
+            [b'\\', escaped, rest @ ..] => {
+                matched |= *escaped == byte;
+                class = rest;
+            }
+            [low, b'-', high, rest @ ..] if *high != b']' => {
+                let (low, high) = if low <= high {
+                    (low, high)
+                } else {
+                    (high, low)
+                };
+                matched |= (*low..=*high).contains(&byte);
+                class = rest;
+            }
+            [single, rest @ ..] => {
+                matched |= *single == byte;
+                class = rest;
+            }
+        }
+    }
+
+    (matched != negate, class)
+}
+
+// Maps a Unix timestamp onto the monotonic clock that deadlines use. A time
+// already in the past becomes now, so an item given it is expired at once.
+pub fn deadline_at_unix_millis(unix_mills: i64) -> Option<Instant> {
+    let now_mills = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as i64);
+    deadline_after(unix_mills.saturating_sub(now_mills).max(0) as u64)
+}
+
+#[derive(Clone, Debug)]
+pub enum KvValue {
+    String(Vec<u8>),
+    List(VecDeque<Vec<u8>>),
+    Hash(HashMap<Vec<u8>, Vec<u8>>),
+}
+
+impl KvValue {
+    // The name TYPE reports for the value.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            KvValue::String(_) => "string",
+            KvValue::List(_) => "list",
+            KvValue::Hash(_) => "hash",
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct KvItem {
-    pub val: String,
+    pub val: KvValue,
     expire_at: Option<Instant>,
 }
 
 impl KvItem {
-    pub fn new(val: String, expire_mills: Option<u64>) -> KvItem {
-        let expire_at = expire_mills.map(|mills| Instant::now() + Duration::from_millis(mills));
+    pub fn new(val: KvValue, expire_mills: Option<u64>) -> KvItem {
+        let expire_at = expire_mills.and_then(deadline_after);
         KvItem { val, expire_at }
     }
 
+    pub fn as_string(&self) -> Result<&Vec<u8>, Error> {
+        match &self.val {
+            KvValue::String(val) => Ok(val),
+            _ => Err(wrong_type_error()),
+        }
+    }
+
+    pub fn as_string_mut(&mut self) -> Result<&mut Vec<u8>, Error> {
+        match &mut self.val {
+            KvValue::String(val) => Ok(val),
+            _ => Err(wrong_type_error()),
+        }
+    }
+
     pub fn expire_after(&mut self, mills: u64) {
-        self.expire_at = Some(Instant::now() + Duration::from_millis(mills));
+        self.expire_at = deadline_after(mills);
+    }
+
+    pub fn as_list(&self) -> Result<&VecDeque<Vec<u8>>, Error> {
+        match &self.val {
+            KvValue::List(list) => Ok(list),
+            _ => Err(wrong_type_error()),
+        }
+    }
+
+    pub fn as_list_mut(&mut self) -> Result<&mut VecDeque<Vec<u8>>, Error> {
+        match &mut self.val {
+            KvValue::List(list) => Ok(list),
+            _ => Err(wrong_type_error()),
+        }
+    }
+
+    pub fn as_hash(&self) -> Result<&HashMap<Vec<u8>, Vec<u8>>, Error> {
+        match &self.val {
+            KvValue::Hash(hash) => Ok(hash),
+            _ => Err(wrong_type_error()),
+        }
+    }
+
+    pub fn as_hash_mut(&mut self) -> Result<&mut HashMap<Vec<u8>, Vec<u8>>, Error> {
+        match &mut self.val {
+            KvValue::Hash(hash) => Ok(hash),
+            _ => Err(wrong_type_error()),
+        }
+    }
+
+    pub fn set_expire_at(&mut self, expire_at: Option<Instant>) {
+        self.expire_at = expire_at;
+    }
+
+    // Returns whether there was a timeout to remove.
+    pub fn persist(&mut self) -> bool {
+        self.expire_at.take().is_some()
+    }
+
+    // Remaining lifetime rounded up to whole milliseconds, so a key that is
+    // still live never reports 0 because of sub-millisecond truncation.
+    pub fn ttl_millis(&self) -> Option<i64> {
+        let remaining = self.expire_at?.saturating_duration_since(Instant::now());
+        Some(remaining.as_micros().div_ceil(1000) as i64)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expire_at.is_some_and(|exp| exp <= Instant::now())
+    }
+}
+
+// A connection blocked in BLPOP/BRPOP. It sleeps on its own `wakeup`, so a
+// push signals exactly the waiter it is meant for.
+#[derive(Default)]
+struct ListWaiter {
+    woken: Mutex<bool>,
+    wakeup: Condvar,
+}
+
+impl ListWaiter {
+    fn wake(&self) {
+        *self.woken.lock().unwrap() = true;
+        self.wakeup.notify_one();
     }
+
+    // Returns early when woken, clearing the flag for the next wait.
+    fn wait(&self, timeout: Duration) {
+        let woken = self.woken.lock().unwrap();
+        let (mut woken, _) = self
+            .wakeup
+            .wait_timeout_while(woken, timeout, |woken| !*woken)
+            .unwrap();
+        *woken = false;
+    }
+}
+
+// Connections blocked on each key, oldest first. Only the head of a key's
+// queue may pop from it; the others wait their turn.
+#[derive(Default)]
+pub struct ListWaiters {
+    queues: Mutex<HashMap<Vec<u8>, VecDeque<Arc<ListWaiter>>>>,
 }
 
+const SWEEP_KEYS_PER_PASS: usize = 200;
+
+// Draws RANDOMKEY makes, reaping each expired key it lands on, before it falls
+// back to picking from the live keys in one pass.
+const RANDOM_KEY_ATTEMPTS: usize = 100;
+
+#[derive(Default)]
 pub struct KvStore {
-    items: HashMap<String, KvItem>,
+    items: HashMap<Vec<u8>, KvItem>,
+    // Keys that were given a TTL, for the sweep to walk without scanning the
+    // whole keyspace. Entries go stale when a key is deleted or persisted and
+    // are dropped once the sweep reaches them.
+    ttl_keys: Vec<Vec<u8>>,
+    ttl_key_set: HashSet<Vec<u8>>,
+    sweep_cursor: usize,
+    list_waiters: Arc<ListWaiters>,
 }
 
 impl KvStore {
     pub fn new() -> KvStore {
-        KvStore {
-            items: HashMap::new(),
-        }
+        KvStore::default()
     }
 
-    pub fn insert(&mut self, key: String, val: KvItem) {
-        println!("set key: {}, val: {:?}", key, val);
+    pub fn insert(&mut self, key: Vec<u8>, val: KvItem) {
+        if val.expire_at.is_some() {
+            self.track_ttl(&key);
+        }
         self.items.insert(key, val);
     }
 
-    pub fn get_clone(&self, key: &str) -> Option<KvItem> {
-        if let Some(val) = self.items.get(key) {
-            println!("get key: {}, val: {:?}", key, val);
-            if let Some(exp) = val.expire_at {
-                if exp > Instant::now() {
-                    return Some(val.clone());
-                }
-            } else {
-                return Some(val.clone());
+    // Overwrites the value of a live key, whatever its type, without touching
+    // its expiry; a missing key is created without one.
+    pub fn set_value_keep_ttl(&mut self, key: Vec<u8>, val: KvValue) {
+        self.expire_if_needed(&key);
+
+        match self.items.get_mut(&key) {
+            Some(item) => item.val = val,
+            None => self.insert(key, KvItem::new(val, None)),
+        }
+    }
+
+    pub fn get_clone(&mut self, key: &[u8]) -> Option<KvItem> {
+        self.expire_if_needed(key);
+
+        self.items.get(key).cloned()
+    }
+
+    // A key whose TTL already passed is dropped but doesn't count as removed.
+    pub fn remove(&mut self, key: &[u8]) -> bool {
+        self.items
+            .remove(key)
+            .is_some_and(|item| !item.is_expired())
+    }
+
+    // Checks the next SWEEP_KEYS_PER_PASS keys with a TTL after where the
+    // previous pass stopped, wrapping around, and returns how many expired
+    // ones it removed.
+    pub fn sweep_expired(&mut self) -> usize {
+        self.sweep(SWEEP_KEYS_PER_PASS)
+    }
+
+    fn sweep(&mut self, max_keys: usize) -> usize {
+        let mut removed = 0;
+        for _ in 0..max_keys.min(self.ttl_keys.len()) {
+            if self.sweep_cursor >= self.ttl_keys.len() {
+                self.sweep_cursor = 0;
+            }
+
+            let key = &self.ttl_keys[self.sweep_cursor];
+            let (keep, expired) = match self.items.get(key) {
+                Some(item) if item.is_expired() => (false, true),
+                Some(item) => (item.expire_at.is_some(), false),
+                None => (false, false),
+            };
+            if keep {
+                self.sweep_cursor += 1;
+                continue;
+            }
+
+            // The last entry takes this slot, so the cursor stays to check it.
+            let key = self.ttl_keys.swap_remove(self.sweep_cursor);
+            self.ttl_key_set.remove(&key);
+            if expired {
+                self.items.remove(&key);
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.ttl_keys.clear();
+        self.ttl_key_set.clear();
+        self.sweep_cursor = 0;
+    }
+
+    // Walks every key while the caller holds the store lock, so with a large
+    // keyspace this stalls all other clients just like KEYS does in Redis.
+    pub fn keys(&self, pattern: &[u8]) -> Vec<Vec<u8>> {
+        self.items
+            .iter()
+            .filter(|(key, item)| !item.is_expired() && glob_match(pattern, key))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    // Reaps every expired key first, which only walks the keys with a TTL, so
+    // the count is exact without checking the whole keyspace.
+    pub fn dbsize(&mut self) -> usize {
+        self.sweep(self.ttl_keys.len());
+        self.items.len()
+    }
+
+    // Like Redis, an expired key that gets drawn is reaped and another one is
+    // drawn. Each draw still walks the map up to the chosen key, there is no
+    // random access into a HashMap.
+    pub fn random_key(&mut self) -> Option<Vec<u8>> {
+        let mut rng = rand::rng();
+        for _ in 0..RANDOM_KEY_ATTEMPTS {
+            let key = self.items.keys().choose(&mut rng)?.clone();
+            if !self.items[&key].is_expired() {
+                return Some(key);
             }
+            self.items.remove(&key);
+        }
+
+        self.items
+            .iter()
+            .filter(|(_, item)| !item.is_expired())
+            .map(|(key, _)| key)
+            .choose(&mut rng)
+            .cloned()
+    }
+
+    // Only the one database exists, and like Redis it is left out when empty.
+    pub fn keyspace_info(&self) -> String {
+        let mut info = String::from("# Keyspace");
+
+        // Redis estimates avg_ttl from the keys its expire cycle samples, here
+        // it is the exact mean of the remaining TTLs in milliseconds.
+        let live = self.items.values().filter(|item| !item.is_expired());
+        let (keys, expires, total_ttl) =
+            live.fold((0, 0, 0), |(keys, expires, total_ttl), item| {
+                let ttl = item.ttl_millis();
+                (
+                    keys + 1,
+                    expires + ttl.is_some() as i64,
+                    total_ttl + ttl.unwrap_or(0),
+                )
+            });
+        if keys > 0 {
+            let avg_ttl = if expires > 0 { total_ttl / expires } else { 0 };
+            info.push_str(&format!(
+                "\r\ndb0:keys={},expires={},avg_ttl={}",
+                keys, expires, avg_ttl
+            ));
+        }
+
+        info
+    }
+
+    // Pops up to `count` elements from the head or tail of a list. A list
+    // left empty is deleted, Redis doesn't keep empty aggregates around.
+    pub fn pop_list(
+        &mut self,
+        key: &[u8],
+        front: bool,
+        count: usize,
+    ) -> Result<Option<Vec<Vec<u8>>>, Error> {
+        self.expire_if_needed(key);
+        let Some(item) = self.items.get_mut(key) else {
+            return Ok(None);
+        };
+
+        let list = item.as_list_mut()?;
+        let n = count.min(list.len());
+        let elements = if front {
+            list.drain(..n).collect()
+        } else {
+            list.drain(list.len() - n..).rev().collect()
+        };
+        if list.is_empty() {
+            self.items.remove(key);
         }
-        None
+        Ok(Some(elements))
+    }
+
+    // Wakes the longest-waiting BLPOP/BRPOP caller blocked on `key`. Call it
+    // with the store lock held after pushing to the list.
+    pub fn notify_list_waiters(&self, key: &[u8]) {
+        let queues = self.list_waiters.queues.lock().unwrap();
+        if let Some(head) = queues.get(key).and_then(|queue| queue.front()) {
+            head.wake();
+        }
+    }
+
+    fn has_list(&self, key: &[u8]) -> bool {
+        self.items
+            .get(key)
+            .is_some_and(|item| !item.is_expired() && matches!(item.val, KvValue::List(_)))
     }
 
-    pub fn do_action<F>(&mut self, key: &str, action_cb: F)
+    pub fn do_action<F>(&mut self, key: &[u8], action_cb: F)
     where
-        F: FnOnce(&str, Option<&mut KvItem>),
+        F: FnOnce(&[u8], Option<&mut KvItem>),
     {
-        if let Some(val) = self.items.get_mut(key) {
-            if let Some(exp) = val.expire_at {
-                if exp > Instant::now() {
-                    action_cb(key, Some(val));
-                } else {
-                    action_cb(key, None);
+        self.expire_if_needed(key);
+        action_cb(key, self.items.get_mut(key));
+        // The action may have given the key a TTL or created it with one.
+        if self
+            .items
+            .get(key)
+            .is_some_and(|item| item.expire_at.is_some())
+        {
+            self.track_ttl(key);
+        }
+    }
+
+    fn track_ttl(&mut self, key: &[u8]) {
+        if !self.ttl_key_set.contains(key) {
+            self.ttl_key_set.insert(key.to_vec());
+            self.ttl_keys.push(key.to_vec());
+        }
+    }
+
+    // Expired keys are removed when they are looked up, otherwise they would
+    // only be hidden and keep their memory forever.
+    fn expire_if_needed(&mut self, key: &[u8]) {
+        if self.items.get(key).is_some_and(|item| item.is_expired()) {
+            self.items.remove(key);
+        }
+    }
+}
+
+// A key and the element popped from the list stored there.
+pub type PoppedElement = (Vec<u8>, Vec<u8>);
+
+// How long a blocked caller sleeps before checking again whether its client
+// is still connected.
+const BLOCKED_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Pops one element from the first of `keys` holding a non-empty list,
+// waiting for one to be pushed until `deadline`, or forever without one.
+// Returns the key popped from and the element, or None on timeout or once
+// `peer_closed` reports that nobody is left to receive the element.
+//
+// The store lock is never held while sleeping. Waiters are served oldest
+// first: a key another waiter is queued on ahead of us is left to that one.
+pub fn blocking_pop<F>(
+    kv_store: &RwLock<KvStore>,
+    keys: &[Vec<u8>],
+    front: bool,
+    deadline: Option<Instant>,
+    peer_closed: F,
+) -> Result<Option<PoppedElement>, Error>
+where
+    F: Fn() -> bool,
+{
+    let waiters = Arc::clone(&kv_store.read().unwrap().list_waiters);
+    let me = Arc::new(ListWaiter::default());
+    let mut registered = false;
+
+    let result = loop {
+        // A client that went away must not take an element with it.
+        if peer_closed() {
+            break Ok(None);
+        }
+
+        // Lock order is always the store, then the queues.
+        let mut store = kv_store.write().unwrap();
+        let mut queues = waiters.queues.lock().unwrap();
+
+        let popped = keys
+            .iter()
+            .filter(|key| {
+                let head = queues.get(*key).and_then(|queue| queue.front());
+                head.is_none_or(|head| Arc::ptr_eq(head, &me))
+            })
+            .find_map(|key| match store.pop_list(key, front, 1) {
+                Ok(Some(mut elements)) => Some(Ok((key.clone(), elements.remove(0)))),
+                Ok(None) => None,
+                Err(e) => Some(Err(e)),
+            });
+        if let Some(popped) = popped {
+            break popped.map(Some);
+        }
+
+        let now = Instant::now();
+        if deadline.is_some_and(|deadline| deadline <= now) {
+            break Ok(None);
+        }
+        // Registering under the store lock means no push can land between
+        // the failed pop and the queue entry, and a wake-up sent before we go
+        // to sleep stays set on `me`.
+        if !registered {
+            for key in keys {
+                queues
+                    .entry(key.clone())
+                    .or_default()
+                    .push_back(Arc::clone(&me));
+            }
+            registered = true;
+        }
+        drop(queues);
+        drop(store);
+
+        let timeout = deadline.map_or(BLOCKED_POLL_INTERVAL, |deadline| {
+            BLOCKED_POLL_INTERVAL.min(deadline - now)
+        });
+        me.wait(timeout);
+    };
+
+    // Leaving hands the keys on: the next waiter of a key that still holds
+    // elements is woken to take them.
+    if registered {
+        let store = kv_store.read().unwrap();
+        let mut queues = waiters.queues.lock().unwrap();
+        for key in keys {
+            let Some(queue) = queues.get_mut(key) else {
+                continue;
+            };
+            queue.retain(|waiter| !Arc::ptr_eq(waiter, &me));
+            match queue.front() {
+                Some(head) if store.has_list(key) => head.wake(),
+                Some(_) => {}
+                None => {
+                    queues.remove(key);
                 }
-            } else {
-                action_cb(key, Some(val));
             }
-        } else {
-            action_cb(key, None);
         }
     }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    use super::*;
+
+    fn string_item(expire_mills: Option<u64>) -> KvItem {
+        KvItem::new(KvValue::String(b"v".to_vec()), expire_mills)
+    }
+
+    #[test]
+    fn expired_key_is_removed_when_read() {
+        let mut store = KvStore::new();
+        store.insert(b"k".to_vec(), string_item(Some(10)));
+        thread::sleep(Duration::from_millis(20));
+
+        assert!(store.get_clone(b"k").is_none());
+        assert_eq!(store.items.len(), 0);
+    }
+
+    #[test]
+    fn expired_key_is_removed_before_an_action_sees_it() {
+        let mut store = KvStore::new();
+        store.insert(b"k".to_vec(), string_item(Some(10)));
+        thread::sleep(Duration::from_millis(20));
+
+        let mut seen = true;
+        store.do_action(b"k", |_, item| seen = item.is_some());
+        assert!(!seen);
+        assert_eq!(store.items.len(), 0);
+    }
+
+    #[test]
+    fn sweep_only_walks_keys_with_a_ttl() {
+        let mut store = KvStore::new();
+        for i in 0..1000 {
+            store.insert(format!("plain:{}", i).into_bytes(), string_item(None));
+        }
+        store.insert(b"gone".to_vec(), string_item(Some(0)));
+        store.insert(b"later".to_vec(), string_item(Some(60_000)));
+
+        assert_eq!(store.sweep_expired(), 1);
+        assert_eq!(store.items.len(), 1001);
+        assert_eq!(store.ttl_keys, vec![b"later".to_vec()]);
+    }
+
+    #[test]
+    fn sweep_is_bounded_per_pass_and_resumes() {
+        let mut store = KvStore::new();
+        for i in 0..SWEEP_KEYS_PER_PASS + 50 {
+            store.insert(format!("gone:{}", i).into_bytes(), string_item(Some(0)));
+        }
+
+        assert_eq!(store.sweep_expired(), SWEEP_KEYS_PER_PASS);
+        assert_eq!(store.sweep_expired(), 50);
+        assert!(store.items.is_empty());
+        assert!(store.ttl_keys.is_empty() && store.ttl_key_set.is_empty());
+    }
+
+    #[test]
+    fn sweep_drops_keys_deleted_or_persisted_since() {
+        let mut store = KvStore::new();
+        store.insert(b"deleted".to_vec(), string_item(Some(60_000)));
+        store.insert(b"persisted".to_vec(), string_item(Some(60_000)));
+        store.remove(b"deleted");
+        store.do_action(b"persisted", |_, item| {
+            item.unwrap().persist();
+        });
+
+        assert_eq!(store.sweep_expired(), 0);
+        assert!(store.ttl_keys.is_empty());
+        assert_eq!(store.dbsize(), 1);
+    }
+
+    #[test]
+    fn ttl_set_through_do_action_is_swept() {
+        let mut store = KvStore::new();
+        store.insert(b"k".to_vec(), string_item(None));
+        store.do_action(b"k", |_, item| item.unwrap().expire_after(0));
+
+        assert_eq!(store.sweep_expired(), 1);
+        assert!(store.items.is_empty());
+    }
+
+    #[test]
+    fn random_key_skips_expired_keys() {
+        let mut store = KvStore::new();
+        for i in 0..100 {
+            store.insert(format!("gone:{}", i).into_bytes(), string_item(Some(0)));
+        }
+        assert_eq!(store.random_key(), None);
+
+        store.insert(
+            b"live".to_vec(),
+            KvItem::new(KvValue::String(b"v".to_vec()), None),
+        );
+        assert_eq!(store.random_key(), Some(b"live".to_vec()));
+    }
+
+    #[test]
+    fn random_key_and_dbsize_agree_on_the_live_keys() {
+        let mut store = KvStore::new();
+        for i in 0..3 {
+            store.insert(format!("live:{}", i).into_bytes(), string_item(None));
+        }
+        for i in 0..2 {
+            store.insert(format!("gone:{}", i).into_bytes(), string_item(Some(0)));
+        }
+
+        for _ in 0..50 {
+            let key = store.random_key().unwrap();
+            assert!(key.starts_with(b"live:"), "{:?}", key);
+        }
+        assert_eq!(store.dbsize(), 3);
+        assert_eq!(store.items.len(), 3);
+    }
+
+    #[test]
+    fn random_key_falls_back_to_a_scan_when_sampling_keeps_missing() {
+        let mut store = KvStore::new();
+        for i in 0..RANDOM_KEY_ATTEMPTS * 20 {
+            store.insert(format!("gone:{}", i).into_bytes(), string_item(Some(0)));
+        }
+        store.insert(b"live".to_vec(), string_item(None));
+
+        assert_eq!(store.random_key(), Some(b"live".to_vec()));
+    }
+
+    #[test]
+    fn keyspace_info_omits_an_empty_db() {
+        let mut store = KvStore::new();
+        assert_eq!(store.keyspace_info(), "# Keyspace");
+
+        store.insert(b"gone".to_vec(), string_item(Some(0)));
+        assert_eq!(store.keyspace_info(), "# Keyspace");
+    }
+
+    #[test]
+    fn keyspace_info_counts_keys_with_a_ttl() {
+        let mut store = KvStore::new();
+        store.insert(b"plain".to_vec(), string_item(None));
+        store.insert(b"short".to_vec(), string_item(Some(10_000)));
+        store.insert(b"long".to_vec(), string_item(Some(30_000)));
+        store.insert(b"gone".to_vec(), string_item(Some(0)));
+
+        let info = store.keyspace_info();
+        let avg_ttl: i64 = info
+            .strip_prefix("# Keyspace\r\ndb0:keys=3,expires=2,avg_ttl=")
+            .unwrap_or_else(|| panic!("{}", info))
+            .parse()
+            .unwrap();
+        assert!((19_000..=20_000).contains(&avg_ttl), "{}", avg_ttl);
+    }
+
+    #[test]
+    fn glob_star_matches_any_run() {
+        assert!(glob_match(b"*", b""));
+        assert!(glob_match(b"*", b"anything"));
+        assert!(glob_match(b"user:*", b"user:1"));
+        assert!(glob_match(b"user:*", b"user:"));
+        assert!(glob_match(b"*:name", b"user:1:name"));
+        assert!(glob_match(b"a**b", b"a-b"));
+        assert!(!glob_match(b"user:*", b"session:1"));
+    }
+
+    #[test]
+    fn glob_question_mark_matches_one_byte() {
+        assert!(glob_match(b"h?llo", b"hello"));
+        assert!(glob_match(b"h?llo", b"hallo"));
+        assert!(!glob_match(b"h?llo", b"hllo"));
+        assert!(!glob_match(b"h?llo", b"heello"));
+    }
+
+    #[test]
+    fn glob_class_matches_listed_bytes_and_ranges() {
+        assert!(glob_match(b"h[ae]llo", b"hello"));
+        assert!(glob_match(b"h[ae]llo", b"hallo"));
+        assert!(!glob_match(b"h[ae]llo", b"hillo"));
+        assert!(glob_match(b"key[0-9]", b"key7"));
+        assert!(glob_match(b"key[9-0]", b"key7"));
+        assert!(!glob_match(b"key[0-9]", b"keyx"));
+    }
+
+    #[test]
+    fn glob_negated_class_excludes_listed_bytes() {
+        assert!(glob_match(b"h[^e]llo", b"hallo"));
+        assert!(!glob_match(b"h[^e]llo", b"hello"));
+        assert!(!glob_match(b"h[^e]llo", b"hllo"));
+    }
+
+    #[test]
+    fn glob_backslash_escapes_metacharacters() {
+        assert!(glob_match(b"a\\*b", b"a*b"));
+        assert!(!glob_match(b"a\\*b", b"axb"));
+        assert!(glob_match(b"a\\?", b"a?"));
+        assert!(glob_match(b"[\\]]", b"]"));
+        // A trailing backslash has nothing to escape and matches itself.
+        assert!(glob_match(b"a\\", b"a\\"));
+    }
+
+    #[test]
+    fn glob_long_pattern_does_not_overflow_the_stack() {
+        let pattern = vec![b'?'; 300_000];
+        assert!(glob_match(&pattern, &vec![b'x'; 300_000]));
+        assert!(!glob_match(&pattern, &vec![b'x'; 299_999]));
+    }
+
+    #[test]
+    fn glob_many_stars_do_not_backtrack_exponentially() {
+        let pattern = format!("{}b", "a*".repeat(30));
+        let key = "a".repeat(100);
+        assert!(!glob_match(pattern.as_bytes(), key.as_bytes()));
+        assert!(glob_match(
+            pattern.as_bytes(),
+            format!("{}b", key).as_bytes()
+        ));
+    }
+
+    fn push(store: &RwLock<KvStore>, key: &[u8], element: &[u8]) {
+        let mut store = store.write().unwrap();
+        let list = VecDeque::from([element.to_vec()]);
+        store.insert(key.to_vec(), KvItem::new(KvValue::List(list), None));
+        store.notify_list_waiters(key);
+    }
+
+    fn spawn_blpop(
+        store: &Arc<RwLock<KvStore>>,
+        key: &[u8],
+    ) -> thread::JoinHandle<Result<Option<PoppedElement>, Error>> {
+        let store = Arc::clone(store);
+        let keys = vec![key.to_vec()];
+        let handle = thread::spawn(move || blocking_pop(&store, &keys, true, None, || false));
+        // Give the waiter time to queue up before the next one.
+        thread::sleep(Duration::from_millis(50));
+        handle
+    }
+
+    #[test]
+    fn blocking_pop_waits_for_a_push_from_another_thread() {
+        let store = Arc::new(RwLock::new(KvStore::new()));
+        let start = Instant::now();
+        let waiter = spawn_blpop(&store, b"q");
+
+        thread::sleep(Duration::from_millis(100));
+        push(&store, b"q", b"job");
+
+        let popped = waiter.join().unwrap().unwrap();
+        assert_eq!(popped, Some((b"q".to_vec(), b"job".to_vec())));
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        assert_eq!(store.write().unwrap().dbsize(), 0);
+    }
+
+    #[test]
+    fn blocking_pop_times_out_with_none() {
+        let store = RwLock::new(KvStore::new());
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(50);
+
+        let popped = blocking_pop(&store, &[b"q".to_vec()], true, Some(deadline), || false);
+        assert_eq!(popped.unwrap(), None);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn blocking_pop_serves_waiters_one_push_at_a_time_in_fifo_order() {
+        let store = Arc::new(RwLock::new(KvStore::new()));
+        let waiters: Vec<_> = (0..3).map(|_| spawn_blpop(&store, b"q")).collect();
+
+        push(&store, b"q", b"first");
+        thread::sleep(Duration::from_millis(50));
+        assert!(waiters[0].is_finished());
+        assert!(!waiters[1].is_finished() && !waiters[2].is_finished());
+
+        push(&store, b"q", b"second");
+        push(&store, b"q2", b"unrelated");
+        thread::sleep(Duration::from_millis(50));
+        assert!(!waiters[2].is_finished());
+        push(&store, b"q", b"third");
+
+        let popped: Vec<_> = waiters
+            .into_iter()
+            .map(|waiter| waiter.join().unwrap().unwrap().unwrap().1)
+            .collect();
+        assert_eq!(
+            popped,
+            [b"first".to_vec(), b"second".to_vec(), b"third".to_vec()]
+        );
+    }
+
+    #[test]
+    fn blocking_pop_leaves_the_element_when_the_client_is_gone() {
+        let store = Arc::new(RwLock::new(KvStore::new()));
+        let closed = Arc::new(AtomicBool::new(false));
+        let gone = {
+            let (store, closed) = (Arc::clone(&store), Arc::clone(&closed));
+            thread::spawn(move || {
+                blocking_pop(&store, &[b"q".to_vec()], true, None, || {
+                    closed.load(Ordering::Relaxed)
+                })
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        let next = spawn_blpop(&store, b"q");
+
+        closed.store(true, Ordering::Relaxed);
+        push(&store, b"q", b"job");
+
+        assert_eq!(gone.join().unwrap().unwrap(), None);
+        let popped = next.join().unwrap().unwrap();
+        assert_eq!(popped, Some((b"q".to_vec(), b"job".to_vec())));
+    }
 }
@@ -0,0 +1,4 @@
+pub mod arg_parser;
+pub mod kv_store;
+pub mod protocol;
+pub mod server;
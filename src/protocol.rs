@@ -1,11 +1,16 @@
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
 use std::io::{BufReader, BufWriter, prelude::*};
 use std::net::TcpStream;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::Error;
 
-use crate::kv_store::{KvItem, KvStore};
+use crate::arg_parser::{ArgParser, parse_int, syntax_error};
+use crate::kv_store::{self, KvItem, KvStore, KvValue, PoppedElement, deadline_at_unix_millis};
 
 #[derive(Copy, Clone)]
 pub enum ServerRole {
@@ -22,11 +27,24 @@ impl fmt::Display for ServerRole {
     }
 }
 
+// Redis' default limit on a single string, 512MB.
+pub const DEFAULT_PROTO_MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+// Same limit Redis applies to the element count of a multibulk request.
+const MAX_MULTIBULK_LEN: usize = 1024 * 1024;
+
+// Longest `*<count>` or `$<len>` header line buffered, as in Redis.
+const MAX_HEADER_LEN: usize = 64 * 1024;
+
 pub struct ServerInfo {
     id: String,
     pub port: u16,
     role: ServerRole,
     replication_offset: usize,
+    start_time: Instant,
+    pub connected_clients: usize,
+    pub enable_debug_command: bool,
+    pub proto_max_bulk_len: usize,
 }
 
 impl ServerInfo {
@@ -36,10 +54,32 @@ impl ServerInfo {
             port,
             role,
             replication_offset: 0,
+            start_time: Instant::now(),
+            connected_clients: 0,
+            enable_debug_command: false,
+            proto_max_bulk_len: DEFAULT_PROTO_MAX_BULK_LEN,
         }
     }
 
-    fn to_string(&self) -> String {
+    pub fn set_role(&mut self, role: ServerRole) {
+        self.role = role;
+    }
+
+    fn server_section(&self) -> String {
+        format!(
+            "# Server\r\nredis_version:7.2.0\r\nprocess_id:{}\r\nrun_id:{}\r\ntcp_port:{}\r\nuptime_in_seconds:{}",
+            std::process::id(),
+            self.id,
+            self.port,
+            self.start_time.elapsed().as_secs()
+        )
+    }
+
+    fn clients_section(&self) -> String {
+        format!("# Clients\r\nconnected_clients:{}", self.connected_clients)
+    }
+
+    fn replication_section(&self) -> String {
         format!(
             "# Replication\r\nrole:{}\r\nmaster_repl_offset:{}\r\nmaster_replid:{}",
             self.role, self.replication_offset, self.id
@@ -49,71 +89,160 @@ impl ServerInfo {
 
 pub struct Request<'a> {
     reader: BufReader<&'a TcpStream>,
-    buffer: String,
+    max_bulk_len: usize,
     pub command: Command,
 }
 
 impl<'a> Request<'a> {
-    pub fn new(stream: &'a TcpStream) -> Request<'a> {
+    pub fn new(stream: &'a TcpStream, max_bulk_len: usize) -> Request<'a> {
         Request {
             reader: BufReader::new(stream),
-            buffer: String::new(),
+            max_bulk_len,
             command: Command::new(String::new(), Vec::new()),
         }
     }
 
-    pub fn read_command(&mut self) -> Result<(), Error> {
-        self.buffer.clear();
+    // Returns Ok(false) when the peer closed the connection between commands,
+    // which is a normal disconnect rather than an error.
+    pub fn read_command(&mut self) -> Result<bool, Error> {
+        let element_cnt = loop {
+            let Some(line) = self.read_line("too big mbulk count string")? else {
+                return Ok(false);
+            };
+
+            let Some(element_cnt) = line.strip_prefix(b"*") else {
+                return Err(unexpected_type('*', &line));
+            };
+            // The count comes from the client, bound it before preallocating.
+            let element_cnt = parse_len(element_cnt)
+                .filter(|&cnt| cnt <= MAX_MULTIBULK_LEN)
+                .ok_or_else(|| ProtocolError("invalid multibulk length".to_string()))?;
+            // Like Redis, an empty array is skipped without a reply.
+            if element_cnt > 0 {
+                break element_cnt;
+            }
+        };
+
+        let mut elements = Vec::with_capacity(element_cnt);
+        for _ in 0..element_cnt {
+            let Some(line) = self.read_line("too big bulk count string")? else {
+                return Err(truncated_command());
+            };
+
+            let Some(len) = line.strip_prefix(b"$") else {
+                return Err(unexpected_type('$', &line));
+            };
+            // Refuse before allocating, a huge length alone could exhaust memory.
+            let len = parse_len(len)
+                .filter(|&len| len <= self.max_bulk_len)
+                .ok_or_else(|| ProtocolError("invalid bulk length".to_string()))?;
 
-        let n = self.reader.read_line(&mut self.buffer)?;
-        if n == 0 {
-            return Err(Error::msg("Connection closed by client"));
+            // Read the exact payload length, it may contain CR, LF or NUL bytes.
+            let mut element = vec![0; len];
+            self.reader
+                .read_exact(&mut element)
+                .map_err(truncated_or_io)?;
+            self.read_crlf()?;
+            elements.push(element);
         }
 
-        let line_cnt = self.buffer.trim_end()[1..].parse::<usize>()?;
-        for _ in 0..line_cnt {
-            let mut line = String::new();
-            let n = self.reader.read_line(&mut line)?;
-            if n == 0 {
-                return Err(Error::msg("Connection closed by client"));
-            }
+        self.command = Command::from_elements(elements);
+        Ok(true)
+    }
 
-            if line.starts_with('$') {
-                let len = line[1..].trim_end().parse::<usize>()?;
-                if len == 0 {
-                    continue;
-                }
-                let n = self.reader.read_line(&mut self.buffer)?;
-                if n == 0 {
-                    return Err(Error::msg("Connection closed by client"));
-                }
-            } else {
-                todo!("Unsupported command format: {}", line);
-            }
+    // Reads one header line without its line ending, None at end of stream. It
+    // works on bytes since a client may well send something that isn't UTF-8.
+    // A line running past MAX_HEADER_LEN is refused with `too_big` rather
+    // than buffered whole.
+    fn read_line(&mut self, too_big: &str) -> Result<Option<Vec<u8>>, Error> {
+        let mut line = Vec::new();
+        let mut limited = (&mut self.reader).take(MAX_HEADER_LEN as u64 + 1);
+        if limited.read_until(b'\n', &mut line)? == 0 {
+            return Ok(None);
+        }
+        if line.len() > MAX_HEADER_LEN && !line.ends_with(b"\n") {
+            return Err(ProtocolError(too_big.to_string()).into());
         }
-        println!("read command: [{}]", self.buffer.trim_end());
 
-        self.command = Command::from_str(&self.buffer);
-        println!("command: {:?}", self.command);
+        if line.ends_with(b"\n") {
+            line.pop();
+            if line.ends_with(b"\r") {
+                line.pop();
+            }
+        }
+        Ok(Some(line))
+    }
 
+    fn read_crlf(&mut self) -> Result<(), Error> {
+        let mut crlf = [0; 2];
+        self.reader.read_exact(&mut crlf).map_err(truncated_or_io)?;
+        if &crlf != b"\r\n" {
+            return Err(ProtocolError("expected CRLF after bulk string".to_string()).into());
+        }
         Ok(())
     }
 }
 
+fn parse_len(len: &[u8]) -> Option<usize> {
+    std::str::from_utf8(len).ok()?.parse().ok()
+}
+
+// Names the first character found where a type prefix was expected, escaped
+// so that an empty line reports its '\r' without breaking the reply.
+fn unexpected_type(expected: char, line: &[u8]) -> Error {
+    let got = String::from_utf8_lossy(line).chars().next().unwrap_or('\r');
+    ProtocolError(format!(
+        "expected '{}', got '{}'",
+        expected,
+        got.escape_debug()
+    ))
+    .into()
+}
+
+// Malformed input that gets an error reply before the connection is closed.
+#[derive(Debug)]
+pub struct ProtocolError(String);
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ERR Protocol error: {}", self.0)
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+fn truncated_command() -> Error {
+    Error::msg("connection closed in the middle of a command")
+}
+
+fn truncated_or_io(e: std::io::Error) -> Error {
+    if e.kind() == std::io::ErrorKind::UnexpectedEof {
+        truncated_command()
+    } else {
+        e.into()
+    }
+}
+
+// Client ids are handed out in connection order starting from 1, as in Redis.
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
 pub struct Response<'a> {
+    client_id: u64,
     writer: BufWriter<&'a TcpStream>,
-    buffer: String,
+    buffer: Vec<u8>,
     state: ResponseState,
     commands: Option<Vec<Command>>,
+    exec_abort: bool,
 }
 
 enum ResponseType<'a> {
     SimpleString(&'a str),
-    BulkString(&'a str),
+    BulkString(&'a [u8]),
     NullBulkString,
     Integer(i64),
     SimpleError(&'a str),
     ArrayHeader(usize),
+    NullArray,
 }
 
 #[derive(Debug, PartialEq)]
@@ -125,39 +254,54 @@ enum ResponseState {
 impl<'a> Response<'a> {
     pub fn new(stream: &'a TcpStream) -> Response<'a> {
         Response {
+            client_id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
             writer: BufWriter::new(stream),
-            buffer: String::new(),
+            buffer: Vec::new(),
             state: ResponseState::Exec,
             commands: None,
+            exec_abort: false,
         }
     }
 
+    pub fn send_protocol_error(&mut self, e: &ProtocolError) -> Result<(), Error> {
+        self.write(ResponseType::SimpleError(e.to_string().as_str()));
+        self.send()
+    }
+
     fn write(&mut self, resp_type: ResponseType) {
         let buffer = &mut self.buffer;
         match resp_type {
             ResponseType::SimpleString(content) => {
-                buffer.push_str(format!("+{}\r\n", content).as_str());
+                buffer.extend_from_slice(format!("+{}\r\n", content).as_bytes());
             }
             ResponseType::BulkString(content) => {
-                buffer.push_str(format!("${}\r\n{}\r\n", content.len(), content).as_str());
+                buffer.extend_from_slice(format!("${}\r\n", content.len()).as_bytes());
+                buffer.extend_from_slice(content);
+                buffer.extend_from_slice(b"\r\n");
             }
             ResponseType::NullBulkString => {
-                buffer.push_str("$-1\r\n");
+                buffer.extend_from_slice(b"$-1\r\n");
             }
             ResponseType::Integer(num) => {
-                buffer.push_str(format!(":{}\r\n", num).as_str());
+                buffer.extend_from_slice(format!(":{}\r\n", num).as_bytes());
             }
             ResponseType::SimpleError(content) => {
-                buffer.push_str(format!("-{}\r\n", content).as_str());
+                // Errors often echo client input, a CR or LF in it would let
+                // the client forge the rest of the reply.
+                let content = content.replace(['\r', '\n'], " ");
+                buffer.extend_from_slice(format!("-{}\r\n", content).as_bytes());
             }
             ResponseType::ArrayHeader(cnt) => {
-                buffer.push_str(format!("*{}\r\n", cnt).as_str());
+                buffer.extend_from_slice(format!("*{}\r\n", cnt).as_bytes());
+            }
+            ResponseType::NullArray => {
+                buffer.extend_from_slice(b"*-1\r\n");
             }
         }
     }
 
     fn send(&mut self) -> Result<(), Error> {
-        self.writer.write_all(self.buffer.as_bytes())?;
+        self.writer.write_all(&self.buffer)?;
         self.writer.flush()?;
         self.buffer.clear();
         Ok(())
@@ -169,18 +313,20 @@ impl<'a> Response<'a> {
         kv_store: &Arc<RwLock<KvStore>>,
         server_info: &Arc<RwLock<ServerInfo>>,
     ) -> Result<(), Error> {
-        println!("State: {:?} | process: {:?}", self.state, command);
-
         match self.state {
             ResponseState::Exec => match command.name.as_str() {
                 "MULTI" => {
                     self.commands = Some(Vec::new());
 
-                    self.exec_command(command, kv_store, server_info)?;
+                    self.exec_locked(command, kv_store, server_info)?;
                     self.state = ResponseState::Queue;
                 }
+                // Sleeping with the locks held would stall every other
+                // connection and the expire thread along with this one.
+                "DEBUG" if is_debug_sleep(command) => self.debug_sleep(command),
+                "BLPOP" | "BRPOP" => self.blocking_pop(command, kv_store),
                 _ => {
-                    self.exec_command(command, kv_store, server_info)?;
+                    self.exec_locked(command, kv_store, server_info)?;
                 }
             },
             ResponseState::Queue => match command.name.as_str() {
@@ -189,8 +335,12 @@ impl<'a> Response<'a> {
                     self.state = ResponseState::Exec;
 
                     if let Some(commands) = &self.commands.take() {
+                        // Hold the store lock for the whole transaction so that
+                        // commands from other connections can't interleave.
+                        let mut kv_store = kv_store.write().unwrap();
+                        let mut server_info = server_info.write().unwrap();
                         for command in commands {
-                            self.exec_command(command, kv_store, server_info)?;
+                            self.exec_command(command, &mut kv_store, &mut server_info)?;
                         }
                     }
 
@@ -211,16 +361,103 @@ impl<'a> Response<'a> {
         Ok(())
     }
 
-    fn queue_command(&mut self, command: &Command) -> Result<(), Error> {
-        println!("State: {:?} | queue: {:?}", self.state, command);
+    fn exec_locked(
+        &mut self,
+        command: &Command,
+        kv_store: &Arc<RwLock<KvStore>>,
+        server_info: &Arc<RwLock<ServerInfo>>,
+    ) -> Result<(), Error> {
+        let mut kv_store = kv_store.write().unwrap();
+        let mut server_info = server_info.write().unwrap();
+        self.exec_command(command, &mut kv_store, &mut server_info)
+    }
+
+    // Existing keys are updated in place so that they keep their TTL.
+    fn incr_by(&mut self, key: &[u8], delta: i64, kv_store: &mut KvStore) {
+        let mut incr_result = None;
+        kv_store.do_action(key, |_, item| {
+            if let Some(item) = item {
+                incr_result = Some(item.as_string_mut().and_then(|val| {
+                    let num = parse_int(val)?
+                        .checked_add(delta)
+                        .ok_or(Error::msg("ERR increment or decrement would overflow"))?;
+                    *val = num.to_string().into_bytes();
+                    Ok(num)
+                }));
+            }
+        });
+
+        match incr_result {
+            Some(Ok(num)) => self.write(ResponseType::Integer(num)),
+            Some(Err(e)) => self.write(ResponseType::SimpleError(e.to_string().as_str())),
+            None => {
+                kv_store.insert(
+                    key.to_vec(),
+                    KvItem::new(KvValue::String(delta.to_string().into_bytes()), None),
+                );
+                self.write(ResponseType::Integer(delta));
+            }
+        }
+    }
+
+    fn blocking_pop(&mut self, command: &Command, kv_store: &Arc<RwLock<KvStore>>) {
+        let (keys, timeout) = match parse_blocking_pop(command) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                self.write(ResponseType::SimpleError(e.to_string().as_str()));
+                return;
+            }
+        };
+
+        // A timeout too large to represent blocks forever, like 0 does.
+        let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+        let front = command.name == "BLPOP";
+        let stream = *self.writer.get_ref();
+        let popped =
+            kv_store::blocking_pop(kv_store, keys, front, deadline, || peer_closed(stream));
+        self.write_blocking_pop(popped);
+    }
 
+    fn write_blocking_pop(&mut self, popped: Result<Option<PoppedElement>, Error>) {
+        match popped {
+            Ok(Some((key, element))) => {
+                self.write(ResponseType::ArrayHeader(2));
+                self.write(ResponseType::BulkString(&key));
+                self.write(ResponseType::BulkString(&element));
+            }
+            Ok(None) => self.write(ResponseType::NullArray),
+            Err(e) => self.write(ResponseType::SimpleError(e.to_string().as_str())),
+        }
+    }
+
+    fn debug_sleep(&mut self, command: &Command) {
+        let Some(seconds) = command.args.get(1) else {
+            self.write(ResponseType::SimpleError(
+                subcommand_syntax_error(command).as_str(),
+            ));
+            return;
+        };
+
+        // Like Redis, anything that doesn't parse as a duration sleeps for 0.
+        let seconds = parse_float(seconds).unwrap_or(0.0);
+        thread::sleep(Duration::try_from_secs_f64(seconds).unwrap_or_default());
+        self.write(ResponseType::SimpleString("OK"));
+    }
+
+    fn queue_command(&mut self, command: &Command) -> Result<(), Error> {
         if self.state != ResponseState::Queue {
             return Err(Error::msg("invalid state for queue command"));
         }
 
         match command.name.as_str() {
             "EXEC" => {
-                if let Some(commands) = &self.commands {
+                if self.exec_abort {
+                    self.write(ResponseType::SimpleError(
+                        "EXECABORT Transaction discarded because of previous errors.",
+                    ));
+                    self.commands = None;
+                    self.exec_abort = false;
+                } else if let Some(commands) = &self.commands {
                     self.write(ResponseType::ArrayHeader(commands.len()));
                 } else {
                     self.write(ResponseType::ArrayHeader(0));
@@ -228,6 +465,13 @@ impl<'a> Response<'a> {
             }
             "DISCARD" => {
                 self.write(ResponseType::SimpleString("OK"));
+                self.exec_abort = false;
+            }
+            "SUBSCRIBE" | "PSUBSCRIBE" | "UNSUBSCRIBE" | "PUNSUBSCRIBE" => {
+                self.write(ResponseType::SimpleError(
+                    format!("ERR {} is not allowed in transactions", command.name).as_str(),
+                ));
+                self.exec_abort = true;
             }
             _ => {
                 if let Some(commands) = &mut self.commands {
@@ -245,11 +489,9 @@ impl<'a> Response<'a> {
     fn exec_command(
         &mut self,
         command: &Command,
-        kv_store: &Arc<RwLock<KvStore>>,
-        server_info: &Arc<RwLock<ServerInfo>>,
+        kv_store: &mut KvStore,
+        server_info: &mut ServerInfo,
     ) -> Result<(), Error> {
-        println!("State: {:?} | exec: {:?}", self.state, command);
-
         if self.state != ResponseState::Exec {
             return Err(Error::msg("invalid state for exec command"));
         }
@@ -270,41 +512,122 @@ impl<'a> Response<'a> {
                     self.write(ResponseType::BulkString(&command.args[0]));
                 }
             }
+            "HELLO" => {
+                // Connections only ever speak RESP2, so that is the only
+                // version HELLO can select.
+                if let Some(version) = command.args.first() {
+                    match parse_int(version) {
+                        Ok(2) => {}
+                        Ok(_) => {
+                            self.write(ResponseType::SimpleError(
+                                "NOPROTO unsupported protocol version",
+                            ));
+                            return Ok(());
+                        }
+                        Err(_) => {
+                            self.write(ResponseType::SimpleError(
+                                "ERR Protocol version is not an integer or out of range",
+                            ));
+                            return Ok(());
+                        }
+                    }
+                }
+                if let Some(option) = command.args.get(1) {
+                    self.write(ResponseType::SimpleError(
+                        format!(
+                            "ERR Syntax error in HELLO option '{}'",
+                            String::from_utf8_lossy(option)
+                        )
+                        .as_str(),
+                    ));
+                    return Ok(());
+                }
+
+                // RESP2 has no map type, so the map goes out as a flat array
+                // of alternating fields and values.
+                let role: &[u8] = match server_info.role {
+                    ServerRole::Master(_) => b"master",
+                    ServerRole::Slave(_) => b"replica",
+                };
+                self.write(ResponseType::ArrayHeader(14));
+                self.write(ResponseType::BulkString(b"server"));
+                self.write(ResponseType::BulkString(b"redis"));
+                self.write(ResponseType::BulkString(b"version"));
+                self.write(ResponseType::BulkString(b"7.2.0"));
+                self.write(ResponseType::BulkString(b"proto"));
+                self.write(ResponseType::Integer(2));
+                self.write(ResponseType::BulkString(b"id"));
+                self.write(ResponseType::Integer(self.client_id as i64));
+                self.write(ResponseType::BulkString(b"mode"));
+                self.write(ResponseType::BulkString(b"standalone"));
+                self.write(ResponseType::BulkString(b"role"));
+                self.write(ResponseType::BulkString(role));
+                self.write(ResponseType::BulkString(b"modules"));
+                self.write(ResponseType::ArrayHeader(0));
+            }
             "SET" => {
                 if command.args.len() < 2 {
                     self.write(ResponseType::SimpleError(
                         "ERR wrong number of arguments for 'set' command",
                     ));
                 } else {
-                    let key = &command.args[0];
-                    let val = &command.args[1];
-                    let mut resp = ResponseType::SimpleString("OK");
-                    if command.args.len() > 2 {
-                        match command.args[2].to_uppercase().as_str() {
-                            "PX" => {
-                                if let Some(expire_mills) =
-                                    command.args.get(3).and_then(|s| s.parse::<u64>().ok())
-                                {
-                                    let mut kv_store = kv_store.write().unwrap();
-                                    kv_store.insert(
-                                        key.clone(),
-                                        KvItem::new(val.clone(), Some(expire_mills)),
-                                    );
-                                } else {
-                                    resp = ResponseType::SimpleError("ERR invalid expire time");
-                                }
-                            }
-                            _ => {
-                                resp = ResponseType::SimpleError(
-                                    "ERR unknown option for 'set' command",
-                                );
-                            }
+                    let mut parser = ArgParser::new(&command.args);
+                    let key = parser.next_string()?;
+                    let val = parser.next_string()?;
+
+                    let result = parse_set_options(&mut parser).and_then(|options| {
+                        let (old_val, done) = set_value(kv_store, key, val, &options)?;
+                        Ok((options, old_val, done))
+                    });
+
+                    // With GET the old value is the reply whether or not the
+                    // key was set.
+                    match result {
+                        Ok((options, Some(old_val), _)) if options.get => {
+                            self.write(ResponseType::BulkString(&old_val))
                         }
-                    } else {
-                        let mut kv_store = kv_store.write().unwrap();
-                        kv_store.insert(key.clone(), KvItem::new(val.clone(), None));
+                        Ok((options, _, done)) if options.get || !done => {
+                            self.write(ResponseType::NullBulkString)
+                        }
+                        Ok(_) => self.write(ResponseType::SimpleString("OK")),
+                        Err(e) => {
+                            self.write(ResponseType::SimpleError(e.to_string().as_str()));
+                        }
+                    }
+                }
+            }
+            "GETSET" => {
+                if command.args.len() != 2 {
+                    self.write(ResponseType::SimpleError(
+                        "ERR wrong number of arguments for 'getset' command",
+                    ));
+                } else {
+                    // Same as SET key val GET, so any TTL is cleared.
+                    let options = SetOptions {
+                        get: true,
+                        ..SetOptions::default()
+                    };
+                    match set_value(kv_store, &command.args[0], &command.args[1], &options) {
+                        Ok((Some(old_val), _)) => self.write(ResponseType::BulkString(&old_val)),
+                        Ok((None, _)) => self.write(ResponseType::NullBulkString),
+                        Err(e) => self.write(ResponseType::SimpleError(e.to_string().as_str())),
+                    }
+                }
+            }
+            "SETNX" => {
+                if command.args.len() != 2 {
+                    self.write(ResponseType::SimpleError(
+                        "ERR wrong number of arguments for 'setnx' command",
+                    ));
+                } else {
+                    let options = SetOptions {
+                        nx: true,
+                        ..SetOptions::default()
+                    };
+                    match set_value(kv_store, &command.args[0], &command.args[1], &options) {
+                        Ok((_, done)) => self.write(ResponseType::Integer(done as i64)),
+                        Err(e) => self.write(ResponseType::SimpleError(e.to_string().as_str())),
                     }
-                    self.write(resp);
                 }
             }
             "GET" => {
@@ -315,108 +638,2591 @@ impl<'a> Response<'a> {
                 } else {
                     let key = &command.args[0];
 
-                    let get_action = |_: &str, item: Option<&mut KvItem>| {
-                        if let Some(item) = item {
-                            self.write(ResponseType::BulkString(&item.val));
-                        } else {
-                            self.write(ResponseType::NullBulkString);
+                    let get_action = |_: &[u8], item: Option<&mut KvItem>| match item
+                        .map(|item| item.as_string())
+                    {
+                        Some(Ok(val)) => self.write(ResponseType::BulkString(val)),
+                        Some(Err(e)) => {
+                            self.write(ResponseType::SimpleError(e.to_string().as_str()))
                         }
+                        None => self.write(ResponseType::NullBulkString),
                     };
 
-                    let mut kv_store = kv_store.write().unwrap();
                     kv_store.do_action(key, get_action);
                 }
             }
-            "INCR" => {
-                if command.args.is_empty() {
+            "INCR" | "DECR" | "INCRBY" | "DECRBY" => {
+                let by_amount = command.name.ends_with("BY");
+                if command.args.len() != 1 + by_amount as usize {
                     self.write(ResponseType::SimpleError(
-                        "ERR wrong number of arguments for 'incr' command",
+                        format!(
+                            "ERR wrong number of arguments for '{}' command",
+                            command.name.to_lowercase()
+                        )
+                        .as_str(),
                     ));
+                    return Ok(());
+                }
+
+                let amount = match command.args.get(1).map(|arg| parse_int(arg)) {
+                    Some(Ok(amount)) => amount,
+                    Some(Err(e)) => {
+                        self.write(ResponseType::SimpleError(e.to_string().as_str()));
+                        return Ok(());
+                    }
+                    None => 1,
+                };
+                let delta = if command.name.starts_with("DECR") {
+                    match amount.checked_neg() {
+                        Some(delta) => delta,
+                        None => {
+                            self.write(ResponseType::SimpleError("ERR decrement would overflow"));
+                            return Ok(());
+                        }
+                    }
                 } else {
-                    let key = &command.args[0];
+                    amount
+                };
 
-                    let mut incr_result = Ok(0);
-                    {
-                        let incr_action = |_: &str, item: Option<&mut KvItem>| {
-                            if let Some(item) = item {
-                                if let Ok(mut num) = item.val.parse::<i64>() {
-                                    num += 1;
-                                    incr_result = Ok(num);
-                                } else {
-                                    incr_result = Err(Error::msg(
-                                        "ERR value is not an integer or out of range",
-                                    ));
-                                }
-                            } else {
-                                incr_result = Ok(1);
+                self.incr_by(&command.args[0], delta, kv_store);
+            }
+            "INCRBYFLOAT" => {
+                if command.args.len() != 2 {
+                    self.write(ResponseType::SimpleError(
+                        "ERR wrong number of arguments for 'incrbyfloat' command",
+                    ));
+                    return Ok(());
+                }
+
+                let key = &command.args[0];
+                let Some(incr) = parse_float(&command.args[1]) else {
+                    self.write(ResponseType::SimpleError("ERR value is not a valid float"));
+                    return Ok(());
+                };
+
+                let mut incr_result = Ok(None);
+                kv_store.do_action(key, |_, item| {
+                    let Some(item) = item else {
+                        return;
+                    };
+                    incr_result = item.as_string_mut().and_then(|val| match parse_float(val) {
+                        Some(num) => format_float(num + incr).map(|num| {
+                            *val = num.clone().into_bytes();
+                            Some(num)
+                        }),
+                        None => Err(Error::msg("ERR value is not a valid float")),
+                    });
+                });
+
+                let incr_result = match incr_result {
+                    Ok(Some(num)) => Ok(num),
+                    Ok(None) => format_float(incr).inspect(|num| {
+                        let val = KvValue::String(num.clone().into_bytes());
+                        kv_store.insert(key.clone(), KvItem::new(val, None));
+                    }),
+                    Err(e) => Err(e),
+                };
+                match incr_result {
+                    Ok(num) => self.write(ResponseType::BulkString(num.as_bytes())),
+                    Err(e) => self.write(ResponseType::SimpleError(e.to_string().as_str())),
+                }
+            }
+            "DEL" => {
+                if command.args.is_empty() {
+                    self.write(ResponseType::SimpleError(
+                        "ERR wrong number of arguments for 'del' command",
+                    ));
+                } else {
+                    let deleted = command
+                        .args
+                        .iter()
+                        .filter(|key| kv_store.remove(key))
+                        .count();
+                    self.write(ResponseType::Integer(deleted as i64));
+                }
+            }
+            "EXISTS" => {
+                if command.args.is_empty() {
+                    self.write(ResponseType::SimpleError(
+                        "ERR wrong number of arguments for 'exists' command",
+                    ));
+                } else {
+                    // Repeated keys are counted once per occurrence, as in Redis.
+                    let mut exists = 0;
+                    for key in &command.args {
+                        kv_store.do_action(key, |_, item| {
+                            if item.is_some() {
+                                exists += 1;
                             }
-                        };
+                        });
+                    }
+                    self.write(ResponseType::Integer(exists));
+                }
+            }
+            "EXPIRE" | "PEXPIRE" => {
+                if command.args.len() != 2 {
+                    self.write(ResponseType::SimpleError(
+                        format!(
+                            "ERR wrong number of arguments for '{}' command",
+                            command.name.to_lowercase()
+                        )
+                        .as_str(),
+                    ));
+                    return Ok(());
+                }
 
-                        let mut kv_store = kv_store.write().unwrap();
-                        kv_store.do_action(key, incr_action);
+                let mut parser = ArgParser::new(&command.args);
+                let key = parser.next_string()?;
+                let ttl = parser.next_string()?;
+                parser.finish()?;
+
+                let unit_mills = if command.name == "EXPIRE" { 1000 } else { 1 };
+                let ttl_mills = match parse_int(ttl) {
+                    Ok(ttl) => ttl.checked_mul(unit_mills),
+                    Err(e) => {
+                        self.write(ResponseType::SimpleError(e.to_string().as_str()));
+                        return Ok(());
                     }
+                };
+                let Some(ttl_mills) = ttl_mills else {
+                    self.write(ResponseType::SimpleError(
+                        format!(
+                            "ERR invalid expire time in '{}' command",
+                            command.name.to_lowercase()
+                        )
+                        .as_str(),
+                    ));
+                    return Ok(());
+                };
 
-                    match incr_result {
-                        Ok(num) => {
-                            let mut kv_store = kv_store.write().unwrap();
-                            kv_store.insert(key.clone(), KvItem::new(num.to_string(), None));
-                            self.write(ResponseType::Integer(num));
-                        }
-                        Err(e) => {
-                            self.write(ResponseType::SimpleError(e.to_string().as_str()));
+                let mut found = false;
+                kv_store.do_action(key, |_, item| {
+                    if let Some(item) = item {
+                        found = true;
+                        if ttl_mills > 0 {
+                            item.expire_after(ttl_mills as u64);
                         }
                     }
+                });
+                // A TTL that is already up deletes the key right away.
+                if found && ttl_mills <= 0 {
+                    kv_store.remove(key);
                 }
+                self.write(ResponseType::Integer(found as i64));
             }
-            "MULTI" => {
-                self.write(ResponseType::SimpleString("OK"));
-            }
-            "EXEC" => {
-                self.write(ResponseType::SimpleError("ERR EXEC without MULTI"));
+            "EXPIREAT" | "PEXPIREAT" => {
+                if command.args.len() != 2 {
+                    self.write(ResponseType::SimpleError(
+                        format!(
+                            "ERR wrong number of arguments for '{}' command",
+                            command.name.to_lowercase()
+                        )
+                        .as_str(),
+                    ));
+                    return Ok(());
+                }
+
+                let key = &command.args[0];
+                let unit_mills = if command.name == "EXPIREAT" { 1000 } else { 1 };
+                let unix_mills = match parse_int(&command.args[1]) {
+                    Ok(timestamp) => timestamp.checked_mul(unit_mills),
+                    Err(e) => {
+                        self.write(ResponseType::SimpleError(e.to_string().as_str()));
+                        return Ok(());
+                    }
+                };
+                let Some(unix_mills) = unix_mills else {
+                    self.write(ResponseType::SimpleError(
+                        format!(
+                            "ERR invalid expire time in '{}' command",
+                            command.name.to_lowercase()
+                        )
+                        .as_str(),
+                    ));
+                    return Ok(());
+                };
+
+                let mut found = false;
+                let mut expired = false;
+                kv_store.do_action(key, |_, item| {
+                    if let Some(item) = item {
+                        found = true;
+                        item.set_expire_at(deadline_at_unix_millis(unix_mills));
+                        expired = item.is_expired();
+                    }
+                });
+                // A timestamp in the past deletes the key right away.
+                if expired {
+                    kv_store.remove(key);
+                }
+                self.write(ResponseType::Integer(found as i64));
             }
-            "DISCARD" => {
-                self.write(ResponseType::SimpleError("ERR DISCARD without MULTI"));
+            "TTL" | "PTTL" => {
+                if command.args.len() != 1 {
+                    self.write(ResponseType::SimpleError(
+                        format!(
+                            "ERR wrong number of arguments for '{}' command",
+                            command.name.to_lowercase()
+                        )
+                        .as_str(),
+                    ));
+                    return Ok(());
+                }
+
+                let mut ttl_mills = None;
+                kv_store.do_action(&command.args[0], |_, item| {
+                    ttl_mills = item.map(|item| item.ttl_millis());
+                });
+
+                let ttl = match ttl_mills {
+                    None => -2,
+                    Some(None) => -1,
+                    Some(Some(mills)) if command.name == "PTTL" => mills,
+                    // Rounded to the nearest second like Redis does.
+                    Some(Some(mills)) => (mills + 500) / 1000,
+                };
+                self.write(ResponseType::Integer(ttl));
             }
-            "INFO" => {
-                if command.args.is_empty() {
-                    todo!("handle info command without args");
+            "PERSIST" => {
+                if command.args.len() != 1 {
+                    self.write(ResponseType::SimpleError(
+                        "ERR wrong number of arguments for 'persist' command",
+                    ));
+                    return Ok(());
                 }
 
-                let section = command.args[0].to_lowercase();
-                match section.as_str() {
-                    "replication" => {
-                        let server_info = server_info.read().unwrap();
-                        self.write(ResponseType::BulkString(server_info.to_string().as_str()));
+                let mut persisted = false;
+                kv_store.do_action(&command.args[0], |_, item| {
+                    persisted = item.is_some_and(|item| item.persist());
+                });
+                self.write(ResponseType::Integer(persisted as i64));
+            }
+            "APPEND" => {
+                if command.args.len() != 2 {
+                    self.write(ResponseType::SimpleError(
+                        "ERR wrong number of arguments for 'append' command",
+                    ));
+                } else {
+                    let key = &command.args[0];
+                    let val = &command.args[1];
+
+                    // Appending in place keeps the key's TTL; a missing key is
+                    // created without one.
+                    let max_len = server_info.proto_max_bulk_len;
+                    let mut new_len = None;
+                    kv_store.do_action(key, |_, item| {
+                        if let Some(item) = item {
+                            new_len = Some(item.as_string_mut().and_then(|old_val| {
+                                if old_val.len() + val.len() > max_len {
+                                    return Err(Error::msg(
+                                        "ERR string exceeds maximum allowed size (proto-max-bulk-len)",
+                                    ));
+                                }
+                                old_val.extend_from_slice(val);
+                                Ok(old_val.len())
+                            }));
+                        }
+                    });
+
+                    let new_len = new_len.unwrap_or_else(|| {
+                        let item = KvItem::new(KvValue::String(val.clone()), None);
+                        kv_store.insert(key.clone(), item);
+                        Ok(val.len())
+                    });
+                    match new_len {
+                        Ok(new_len) => self.write(ResponseType::Integer(new_len as i64)),
+                        Err(e) => self.write(ResponseType::SimpleError(e.to_string().as_str())),
                     }
-                    _ => {
-                        todo!("other section for INFO command: {}", section);
+                }
+            }
+            "STRLEN" => {
+                if command.args.len() != 1 {
+                    self.write(ResponseType::SimpleError(
+                        "ERR wrong number of arguments for 'strlen' command",
+                    ));
+                } else {
+                    // Values are raw bytes, so this is the byte length.
+                    let mut len = Ok(0);
+                    kv_store.do_action(&command.args[0], |_, item| {
+                        if let Some(item) = item {
+                            len = item.as_string().map(|val| val.len());
+                        }
+                    });
+                    match len {
+                        Ok(len) => self.write(ResponseType::Integer(len as i64)),
+                        Err(e) => self.write(ResponseType::SimpleError(e.to_string().as_str())),
                     }
                 }
             }
-            _ => {
-                self.write(ResponseType::SimpleError("ERR unknown command"));
+            "GETRANGE" => {
+                if command.args.len() != 3 {
+                    self.write(ResponseType::SimpleError(
+                        "ERR wrong number of arguments for 'getrange' command",
+                    ));
+                    return Ok(());
+                }
+
+                let range = parse_int(&command.args[1])
+                    .and_then(|start| Ok((start, parse_int(&command.args[2])?)));
+                let (start, end) = match range {
+                    Ok(range) => range,
+                    Err(e) => {
+                        self.write(ResponseType::SimpleError(e.to_string().as_str()));
+                        return Ok(());
+                    }
+                };
+
+                let mut val = Ok(Vec::new());
+                kv_store.do_action(&command.args[0], |_, item| {
+                    if let Some(item) = item {
+                        val = item.as_string().cloned();
+                    }
+                });
+                match val {
+                    Ok(val) => self.write(ResponseType::BulkString(byte_range(&val, start, end))),
+                    Err(e) => self.write(ResponseType::SimpleError(e.to_string().as_str())),
+                }
             }
-        }
+            "SETRANGE" => {
+                if command.args.len() != 3 {
+                    self.write(ResponseType::SimpleError(
+                        "ERR wrong number of arguments for 'setrange' command",
+                    ));
+                    return Ok(());
+                }
 
-        Ok(())
-    }
-}
+                let key = &command.args[0];
+                let val = &command.args[2];
+                let offset = match parse_int(&command.args[1]) {
+                    Ok(offset) if offset >= 0 => offset as usize,
+                    Ok(_) => {
+                        self.write(ResponseType::SimpleError("ERR offset is out of range"));
+                        return Ok(());
+                    }
+                    Err(e) => {
+                        self.write(ResponseType::SimpleError(e.to_string().as_str()));
+                        return Ok(());
+                    }
+                };
+                if !val.is_empty() && offset + val.len() > server_info.proto_max_bulk_len {
+                    self.write(ResponseType::SimpleError(
+                        "ERR string exceeds maximum allowed size (proto-max-bulk-len)",
+                    ));
+                    return Ok(());
+                }
 
-#[derive(Debug, Clone)]
-pub struct Command {
-    name: String,
-    args: Vec<String>,
-}
+                // Writing in place keeps the key's TTL.
+                let mut new_len = None;
+                kv_store.do_action(key, |_, item| {
+                    if let Some(item) = item {
+                        new_len = Some(item.as_string_mut().map(|old_val| {
+                            overwrite_at(old_val, offset, val);
+                            old_val.len()
+                        }));
+                    }
+                });
 
-impl Command {
-    fn new(name: String, args: Vec<String>) -> Command {
-        Command { name, args }
-    }
+                // An empty value doesn't create a missing key.
+                let new_len = match new_len {
+                    Some(new_len) => new_len,
+                    None if val.is_empty() => Ok(0),
+                    None => {
+                        let mut new_val = Vec::new();
+                        overwrite_at(&mut new_val, offset, val);
+                        let new_len = new_val.len();
+                        let item = KvItem::new(KvValue::String(new_val), None);
+                        kv_store.insert(key.clone(), item);
+                        Ok(new_len)
+                    }
+                };
+                match new_len {
+                    Ok(new_len) => self.write(ResponseType::Integer(new_len as i64)),
+                    Err(e) => self.write(ResponseType::SimpleError(e.to_string().as_str())),
+                }
+            }
+            "KEYS" => {
+                if command.args.len() != 1 {
+                    self.write(ResponseType::SimpleError(
+                        "ERR wrong number of arguments for 'keys' command",
+                    ));
+                } else {
+                    let keys = kv_store.keys(&command.args[0]);
+                    self.write(ResponseType::ArrayHeader(keys.len()));
+                    for key in &keys {
+                        self.write(ResponseType::BulkString(key));
+                    }
+                }
+            }
+            "RPUSH" | "LPUSH" => {
+                if command.args.len() < 2 {
+                    self.write(ResponseType::SimpleError(
+                        format!(
+                            "ERR wrong number of arguments for '{}' command",
+                            command.name.to_lowercase()
+                        )
+                        .as_str(),
+                    ));
+                    return Ok(());
+                }
 
-    fn from_str(command_str: &str) -> Command {
-        let parts: Vec<&str> = command_str.trim().split_whitespace().collect();
-        let name = parts[1].to_string().to_uppercase();
-        let args: Vec<String> = parts[2..].iter().map(|&s| s.to_string()).collect();
-        Command::new(name, args)
+                let key = &command.args[0];
+                let push_front = command.name == "LPUSH";
+                let push = |list: &mut VecDeque<Vec<u8>>| {
+                    for element in &command.args[1..] {
+                        if push_front {
+                            list.push_front(element.clone());
+                        } else {
+                            list.push_back(element.clone());
+                        }
+                    }
+                    list.len()
+                };
+
+                let mut new_len = None;
+                kv_store.do_action(key, |_, item| {
+                    if let Some(item) = item {
+                        new_len = Some(item.as_list_mut().map(push));
+                    }
+                });
+
+                let new_len = new_len.unwrap_or_else(|| {
+                    let mut list = VecDeque::new();
+                    let new_len = push(&mut list);
+                    kv_store.insert(key.clone(), KvItem::new(KvValue::List(list), None));
+                    Ok(new_len)
+                });
+                match new_len {
+                    Ok(new_len) => {
+                        kv_store.notify_list_waiters(key);
+                        self.write(ResponseType::Integer(new_len as i64));
+                    }
+                    Err(e) => self.write(ResponseType::SimpleError(e.to_string().as_str())),
+                }
+            }
+            "LPOP" | "RPOP" => {
+                if command.args.is_empty() || command.args.len() > 2 {
+                    self.write(ResponseType::SimpleError(
+                        format!(
+                            "ERR wrong number of arguments for '{}' command",
+                            command.name.to_lowercase()
+                        )
+                        .as_str(),
+                    ));
+                    return Ok(());
+                }
+
+                let key = &command.args[0];
+                let count = match command.args.get(1).map(|arg| parse_int(arg)) {
+                    Some(Ok(count)) if count >= 0 => Some(count as usize),
+                    Some(Ok(_)) => {
+                        self.write(ResponseType::SimpleError(
+                            "ERR value is out of range, must be positive",
+                        ));
+                        return Ok(());
+                    }
+                    Some(Err(e)) => {
+                        self.write(ResponseType::SimpleError(e.to_string().as_str()));
+                        return Ok(());
+                    }
+                    None => None,
+                };
+
+                let popped = kv_store.pop_list(key, command.name == "LPOP", count.unwrap_or(1));
+                match (popped, count) {
+                    (Ok(Some(elements)), Some(_)) => {
+                        self.write(ResponseType::ArrayHeader(elements.len()));
+                        for element in &elements {
+                            self.write(ResponseType::BulkString(element));
+                        }
+                    }
+                    (Ok(Some(elements)), None) => {
+                        self.write(ResponseType::BulkString(&elements[0]));
+                    }
+                    (Ok(None), Some(_)) => self.write(ResponseType::NullArray),
+                    (Ok(None), None) => self.write(ResponseType::NullBulkString),
+                    (Err(e), _) => self.write(ResponseType::SimpleError(e.to_string().as_str())),
+                }
+            }
+            // Only reached inside a transaction, where blocking would hold up
+            // everyone, so this pops right away or replies with a null array.
+            "BLPOP" | "BRPOP" => {
+                let keys = match parse_blocking_pop(command) {
+                    Ok((keys, _)) => keys,
+                    Err(e) => {
+                        self.write(ResponseType::SimpleError(e.to_string().as_str()));
+                        return Ok(());
+                    }
+                };
+
+                let front = command.name == "BLPOP";
+                let popped = keys
+                    .iter()
+                    .find_map(|key| match kv_store.pop_list(key, front, 1) {
+                        Ok(Some(mut elements)) => Some(Ok((key.clone(), elements.remove(0)))),
+                        Ok(None) => None,
+                        Err(e) => Some(Err(e)),
+                    });
+                self.write_blocking_pop(popped.transpose());
+            }
+            "LLEN" => {
+                if command.args.len() != 1 {
+                    self.write(ResponseType::SimpleError(
+                        "ERR wrong number of arguments for 'llen' command",
+                    ));
+                } else {
+                    let mut len = Ok(0);
+                    kv_store.do_action(&command.args[0], |_, item| {
+                        if let Some(item) = item {
+                            len = item.as_list().map(|list| list.len());
+                        }
+                    });
+                    match len {
+                        Ok(len) => self.write(ResponseType::Integer(len as i64)),
+                        Err(e) => self.write(ResponseType::SimpleError(e.to_string().as_str())),
+                    }
+                }
+            }
+            "LRANGE" => {
+                if command.args.len() != 3 {
+                    self.write(ResponseType::SimpleError(
+                        "ERR wrong number of arguments for 'lrange' command",
+                    ));
+                    return Ok(());
+                }
+
+                let range = parse_int(&command.args[1])
+                    .and_then(|start| Ok((start, parse_int(&command.args[2])?)));
+                let (start, stop) = match range {
+                    Ok(range) => range,
+                    Err(e) => {
+                        self.write(ResponseType::SimpleError(e.to_string().as_str()));
+                        return Ok(());
+                    }
+                };
+
+                let mut elements = Ok(Vec::new());
+                kv_store.do_action(&command.args[0], |_, item| {
+                    if let Some(item) = item {
+                        elements = item.as_list().map(|list| {
+                            let (start, stop) = index_range(list.len(), start, stop);
+                            list.range(start..stop).cloned().collect()
+                        });
+                    }
+                });
+                match elements {
+                    Ok(elements) => {
+                        self.write(ResponseType::ArrayHeader(elements.len()));
+                        for element in &elements {
+                            self.write(ResponseType::BulkString(element));
+                        }
+                    }
+                    Err(e) => self.write(ResponseType::SimpleError(e.to_string().as_str())),
+                }
+            }
+            "HSET" => {
+                if command.args.len() < 3 || command.args.len().is_multiple_of(2) {
+                    self.write(ResponseType::SimpleError(
+                        "ERR wrong number of arguments for 'hset' command",
+                    ));
+                    return Ok(());
+                }
+
+                let key = &command.args[0];
+                // Returns how many of the fields weren't in the hash before.
+                let set_fields = |hash: &mut HashMap<Vec<u8>, Vec<u8>>| {
+                    let pairs = command.args[1..].chunks_exact(2);
+                    pairs
+                        .filter(|pair| hash.insert(pair[0].clone(), pair[1].clone()).is_none())
+                        .count()
+                };
+
+                let mut added = None;
+                kv_store.do_action(key, |_, item| {
+                    if let Some(item) = item {
+                        added = Some(item.as_hash_mut().map(set_fields));
+                    }
+                });
+
+                let added = added.unwrap_or_else(|| {
+                    let mut hash = HashMap::new();
+                    let added = set_fields(&mut hash);
+                    kv_store.insert(key.clone(), KvItem::new(KvValue::Hash(hash), None));
+                    Ok(added)
+                });
+                match added {
+                    Ok(added) => self.write(ResponseType::Integer(added as i64)),
+                    Err(e) => self.write(ResponseType::SimpleError(e.to_string().as_str())),
+                }
+            }
+            "HGET" => {
+                if command.args.len() != 2 {
+                    self.write(ResponseType::SimpleError(
+                        "ERR wrong number of arguments for 'hget' command",
+                    ));
+                    return Ok(());
+                }
+
+                let mut val = Ok(None);
+                kv_store.do_action(&command.args[0], |_, item| {
+                    if let Some(item) = item {
+                        val = item
+                            .as_hash()
+                            .map(|hash| hash.get(&command.args[1]).cloned());
+                    }
+                });
+                match val {
+                    Ok(Some(val)) => self.write(ResponseType::BulkString(&val)),
+                    Ok(None) => self.write(ResponseType::NullBulkString),
+                    Err(e) => self.write(ResponseType::SimpleError(e.to_string().as_str())),
+                }
+            }
+            "HGETALL" => {
+                if command.args.len() != 1 {
+                    self.write(ResponseType::SimpleError(
+                        "ERR wrong number of arguments for 'hgetall' command",
+                    ));
+                    return Ok(());
+                }
+
+                let mut hash = Ok(HashMap::new());
+                kv_store.do_action(&command.args[0], |_, item| {
+                    if let Some(item) = item {
+                        hash = item.as_hash().cloned();
+                    }
+                });
+                // RESP2 has no map type, so fields and values alternate.
+                match hash {
+                    Ok(hash) => {
+                        self.write(ResponseType::ArrayHeader(hash.len() * 2));
+                        for (field, val) in &hash {
+                            self.write(ResponseType::BulkString(field));
+                            self.write(ResponseType::BulkString(val));
+                        }
+                    }
+                    Err(e) => self.write(ResponseType::SimpleError(e.to_string().as_str())),
+                }
+            }
+            "HDEL" => {
+                if command.args.len() < 2 {
+                    self.write(ResponseType::SimpleError(
+                        "ERR wrong number of arguments for 'hdel' command",
+                    ));
+                    return Ok(());
+                }
+
+                let key = &command.args[0];
+                let mut removed = Ok(0);
+                let mut emptied = false;
+                kv_store.do_action(key, |_, item| {
+                    if let Some(item) = item {
+                        removed = item.as_hash_mut().map(|hash| {
+                            let removed = command.args[1..]
+                                .iter()
+                                .filter(|field| hash.remove(*field).is_some())
+                                .count();
+                            emptied = hash.is_empty();
+                            removed
+                        });
+                    }
+                });
+                // Like an emptied list, an emptied hash doesn't keep its key.
+                if emptied {
+                    kv_store.remove(key);
+                }
+
+                match removed {
+                    Ok(removed) => self.write(ResponseType::Integer(removed as i64)),
+                    Err(e) => self.write(ResponseType::SimpleError(e.to_string().as_str())),
+                }
+            }
+            "TYPE" => {
+                if command.args.len() != 1 {
+                    self.write(ResponseType::SimpleError(
+                        "ERR wrong number of arguments for 'type' command",
+                    ));
+                } else {
+                    let mut type_name = "none";
+                    kv_store.do_action(&command.args[0], |_, item| {
+                        if let Some(item) = item {
+                            type_name = item.val.type_name();
+                        }
+                    });
+                    self.write(ResponseType::SimpleString(type_name));
+                }
+            }
+            "DBSIZE" => {
+                self.write(ResponseType::Integer(kv_store.dbsize() as i64));
+            }
+            "FLUSHDB" | "FLUSHALL" => {
+                // There is only one database, so both clear the whole store.
+                // Keys are freed right away, ASYNC is accepted but not honored.
+                let mut parser = ArgParser::new(&command.args);
+                if !parser.try_flag("ASYNC") {
+                    parser.try_flag("SYNC");
+                }
+                if let Err(e) = parser.finish() {
+                    self.write(ResponseType::SimpleError(e.to_string().as_str()));
+                    return Ok(());
+                }
+
+                kv_store.clear();
+                self.write(ResponseType::SimpleString("OK"));
+            }
+            "RANDOMKEY" => match kv_store.random_key() {
+                Some(key) => self.write(ResponseType::BulkString(&key)),
+                None => self.write(ResponseType::NullBulkString),
+            },
+            "MULTI" => {
+                self.write(ResponseType::SimpleString("OK"));
+            }
+            "EXEC" => {
+                self.write(ResponseType::SimpleError("ERR EXEC without MULTI"));
+            }
+            "DISCARD" => {
+                self.write(ResponseType::SimpleError("ERR DISCARD without MULTI"));
+            }
+            "INFO" => {
+                // There are no commandstats/latencystats sections, so "all" and
+                // "everything" cover the same sections as "default".
+                const DEFAULT_SECTIONS: [&str; 4] =
+                    ["server", "clients", "replication", "keyspace"];
+
+                let mut sections = vec![];
+                if command.args.is_empty() {
+                    sections.extend(DEFAULT_SECTIONS);
+                }
+                for arg in &command.args {
+                    match String::from_utf8_lossy(arg).to_lowercase().as_str() {
+                        "default" | "all" | "everything" => sections.extend(DEFAULT_SECTIONS),
+                        "server" => sections.push("server"),
+                        "clients" => sections.push("clients"),
+                        "replication" => sections.push("replication"),
+                        "keyspace" => sections.push("keyspace"),
+                        _ => {}
+                    }
+                }
+
+                let mut info = vec![];
+                for section in DEFAULT_SECTIONS {
+                    if !sections.contains(&section) {
+                        continue;
+                    }
+                    info.push(match section {
+                        "server" => server_info.server_section(),
+                        "clients" => server_info.clients_section(),
+                        "replication" => server_info.replication_section(),
+                        _ => kv_store.keyspace_info(),
+                    });
+                }
+                self.write(ResponseType::BulkString(info.join("\r\n\r\n").as_bytes()));
+            }
+            "REPLICAOF" | "SLAVEOF" => {
+                if command.args.len() != 2 {
+                    self.write(ResponseType::SimpleError(
+                        format!(
+                            "ERR wrong number of arguments for '{}' command",
+                            command.name.to_lowercase()
+                        )
+                        .as_str(),
+                    ));
+                } else if command.args[0].eq_ignore_ascii_case(b"NO")
+                    && command.args[1].eq_ignore_ascii_case(b"ONE")
+                {
+                    server_info.set_role(ServerRole::Master("master"));
+                    self.write(ResponseType::SimpleString("OK"));
+                } else if !parse_int(&command.args[1]).is_ok_and(|port| (0..=65535).contains(&port))
+                {
+                    self.write(ResponseType::SimpleError("ERR Invalid master port"));
+                } else {
+                    server_info.set_role(ServerRole::Slave("slave"));
+                    self.write(ResponseType::SimpleString("OK"));
+                }
+            }
+            "DEBUG" => {
+                if command.args.is_empty() {
+                    self.write(ResponseType::SimpleError(
+                        "ERR wrong number of arguments for 'debug' command",
+                    ));
+                    return Ok(());
+                }
+
+                let subcommand = String::from_utf8_lossy(&command.args[0]).to_uppercase();
+                match subcommand.as_str() {
+                    "JMAP" => {
+                        self.write(ResponseType::SimpleString("OK"));
+                    }
+                    // Inside EXEC the transaction keeps its locks while it sleeps.
+                    "SLEEP" => self.debug_sleep(command),
+                    "SEGFAULT" | "PANIC" | "OOM" if !server_info.enable_debug_command => {
+                        self.write(ResponseType::SimpleError("ERR DEBUG command not allowed"));
+                    }
+                    "SEGFAULT" => std::process::abort(),
+                    "PANIC" => {
+                        // Exit rather than unwind, a panic here would poison the store lock.
+                        eprintln!("DEBUG PANIC called");
+                        std::process::exit(1);
+                    }
+                    "OOM" => std::alloc::handle_alloc_error(std::alloc::Layout::new::<[u8; 1]>()),
+                    _ => {
+                        self.write(ResponseType::SimpleError(
+                            subcommand_syntax_error(command).as_str(),
+                        ));
+                    }
+                }
+            }
+            _ => {
+                let mut args = String::new();
+                for arg in &command.args {
+                    if args.len() >= 128 {
+                        break;
+                    }
+                    let arg: String = String::from_utf8_lossy(arg)
+                        .chars()
+                        .take(128 - args.len())
+                        .collect();
+                    args.push_str(format!("'{}' ", arg).as_str());
+                }
+                let name: String = command.raw_name.chars().take(128).collect();
+                self.write(ResponseType::SimpleError(
+                    format!(
+                        "ERR unknown command '{}', with args beginning with: {}",
+                        name, args
+                    )
+                    .as_str(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Resolves GETRANGE's inclusive, possibly negative offsets the way Redis
+// does: both are clamped into the value, and an empty range gives "".
+fn byte_range(val: &[u8], start: i64, end: i64) -> &[u8] {
+    let len = val.len() as i64;
+    if len == 0 || (start < 0 && end < 0 && start > end) {
+        return &[];
+    }
+
+    let start = if start < 0 { len + start } else { start }.max(0);
+    let end = if end < 0 { len + end } else { end }.clamp(0, len - 1);
+    if start > end {
+        return &[];
+    }
+    &val[start as usize..=end as usize]
+}
+
+// Resolves LRANGE's inclusive, possibly negative indices into a half-open
+// range of positions in a list of `len` elements, empty if none are in it.
+fn index_range(len: usize, start: i64, stop: i64) -> (usize, usize) {
+    let len = len as i64;
+    let start = if start < 0 { len + start } else { start }.max(0);
+    let stop = if stop < 0 { len + stop } else { stop }.min(len - 1);
+    if start > stop {
+        return (0, 0);
+    }
+    (start as usize, stop as usize + 1)
+}
+
+// Writes `data` over `val` at `offset`, zero-padding any gap past the end.
+fn overwrite_at(val: &mut Vec<u8>, offset: usize, data: &[u8]) {
+    if data.is_empty() {
+        return;
+    }
+    if val.len() < offset + data.len() {
+        val.resize(offset + data.len(), 0);
+    }
+    val[offset..offset + data.len()].copy_from_slice(data);
+}
+
+// Splits BLPOP/BRPOP arguments into the keys and the timeout, which is in
+// possibly fractional seconds, with 0 meaning no timeout.
+fn parse_blocking_pop(command: &Command) -> Result<(&[Vec<u8>], Option<Duration>), Error> {
+    let Some((timeout, keys)) = command
+        .args
+        .split_last()
+        .filter(|(_, keys)| !keys.is_empty())
+    else {
+        return Err(Error::msg(format!(
+            "ERR wrong number of arguments for '{}' command",
+            command.name.to_lowercase()
+        )));
+    };
+
+    let timeout = parse_float(timeout)
+        .filter(|timeout| timeout.is_finite())
+        .ok_or(Error::msg("ERR timeout is not a float or out of range"))?;
+    if timeout < 0.0 {
+        return Err(Error::msg("ERR timeout is negative"));
+    }
+    // A timeout too large for a Duration is as good as blocking forever.
+    let timeout = Duration::try_from_secs_f64(timeout)
+        .ok()
+        .filter(|timeout| !timeout.is_zero());
+    Ok((keys, timeout))
+}
+
+// Peeks without blocking: a clean EOF or a reset means the client is gone,
+// while pending bytes are just its next command.
+fn peer_closed(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let mut byte = [0; 1];
+    let closed = match stream.peek(&mut byte) {
+        Ok(n) => n == 0,
+        Err(e) => e.kind() != std::io::ErrorKind::WouldBlock,
+    };
+    let _ = stream.set_nonblocking(false);
+    closed
+}
+
+// The reply Redis gives for a subcommand it doesn't know or that got the
+// wrong number of arguments, naming the subcommand as the client sent it.
+fn subcommand_syntax_error(command: &Command) -> String {
+    let subcommand: String = String::from_utf8_lossy(&command.args[0])
+        .chars()
+        .take(128)
+        .collect();
+    format!(
+        "ERR unknown subcommand or wrong number of arguments for '{}'. Try {} HELP.",
+        subcommand, command.name
+    )
+}
+
+fn is_debug_sleep(command: &Command) -> bool {
+    command
+        .args
+        .first()
+        .is_some_and(|subcommand| subcommand.eq_ignore_ascii_case(b"SLEEP"))
+}
+
+fn parse_float(arg: &[u8]) -> Option<f64> {
+    let num = std::str::from_utf8(arg).ok()?.parse::<f64>().ok()?;
+    (!num.is_nan()).then_some(num)
+}
+
+// Redis prints the result from a long double with "%.17Lf" and trims the
+// trailing zeros and point. Rust's shortest round-trip Display of an f64 also
+// never uses an exponent and drops a trailing ".0", so 3.0 gives "3" and 5e3
+// gives "5000" in both. Without the long double's extra precision an f64
+// rounding error can show though: 0.1 plus 0.2 is "0.3" in Redis but
+// "0.30000000000000004" here.
+fn format_float(num: f64) -> Result<String, Error> {
+    if num.is_finite() {
+        Ok(num.to_string())
+    } else {
+        Err(Error::msg("ERR increment would produce NaN or Infinity"))
+    }
+}
+
+#[derive(Default)]
+struct SetOptions {
+    expire_mills: Option<u64>,
+    expire_at_mills: Option<i64>,
+    keep_ttl: bool,
+    get: bool,
+    nx: bool,
+    xx: bool,
+}
+
+// Options may come in any order; repeated, conflicting or unknown ones are
+// all a syntax error.
+fn parse_set_options(parser: &mut ArgParser) -> Result<SetOptions, Error> {
+    let mut options = SetOptions::default();
+
+    loop {
+        if parser.try_flag("NX") {
+            if options.xx {
+                return Err(syntax_error());
+            }
+            options.nx = true;
+            continue;
+        }
+        if parser.try_flag("XX") {
+            if options.nx {
+                return Err(syntax_error());
+            }
+            options.xx = true;
+            continue;
+        }
+        if parser.try_flag("GET") {
+            if options.get {
+                return Err(syntax_error());
+            }
+            options.get = true;
+            continue;
+        }
+        if parser.try_flag("KEEPTTL") {
+            if options.keep_ttl
+                || options.expire_mills.is_some()
+                || options.expire_at_mills.is_some()
+            {
+                return Err(syntax_error());
+            }
+            options.keep_ttl = true;
+            continue;
+        }
+
+        // EXAT and PXAT take a Unix timestamp rather than a TTL.
+        let (time, unit_mills, absolute) = if let Some(ttl) = parser.try_option_with_value("EX")? {
+            (ttl, 1000, false)
+        } else if let Some(ttl) = parser.try_option_with_value("PX")? {
+            (ttl, 1, false)
+        } else if let Some(at) = parser.try_option_with_value("EXAT")? {
+            (at, 1000, true)
+        } else if let Some(at) = parser.try_option_with_value("PXAT")? {
+            (at, 1, true)
+        } else {
+            break;
+        };
+        if options.expire_mills.is_some() || options.expire_at_mills.is_some() || options.keep_ttl {
+            return Err(syntax_error());
+        }
+
+        let time = parse_int(time)?;
+        if time <= 0 {
+            return Err(Error::msg("ERR invalid expire time in 'set' command"));
+        }
+        let mills = time
+            .checked_mul(unit_mills)
+            .ok_or(Error::msg("ERR invalid expire time in 'set' command"))?;
+        if absolute {
+            options.expire_at_mills = Some(mills);
+        } else {
+            options.expire_mills = Some(mills as u64);
+        }
+    }
+    parser.finish()?;
+
+    Ok(options)
+}
+
+// Stores a value the way SET does with the given options, replacing a value
+// of any type. Returns the old string value, when there was a live one, and
+// whether NX/XX allowed the set. With GET, an old non-string is WRONGTYPE.
+fn set_value(
+    kv_store: &mut KvStore,
+    key: &[u8],
+    val: &[u8],
+    options: &SetOptions,
+) -> Result<(Option<Vec<u8>>, bool), Error> {
+    let mut old_val = Ok(None);
+    let mut exists = false;
+    kv_store.do_action(key, |_, item| {
+        if let Some(item) = item {
+            exists = true;
+            old_val = item.as_string().map(|val| Some(val.clone()));
+        }
+    });
+    let old_val = match old_val {
+        Err(e) if options.get => return Err(e),
+        old_val => old_val.unwrap_or(None),
+    };
+
+    if (options.nx && exists) || (options.xx && !exists) {
+        return Ok((old_val, false));
+    }
+
+    let val = KvValue::String(val.to_vec());
+    if options.keep_ttl {
+        kv_store.set_value_keep_ttl(key.to_vec(), val);
+    } else {
+        let mut item = KvItem::new(val, options.expire_mills);
+        if let Some(unix_mills) = options.expire_at_mills {
+            item.set_expire_at(deadline_at_unix_millis(unix_mills));
+        }
+
+        // A deadline that has already passed leaves no key behind.
+        if item.is_expired() {
+            kv_store.remove(key);
+        } else {
+            kv_store.insert(key.to_vec(), item);
+        }
+    }
+
+    Ok((old_val, true))
+}
+
+#[derive(Debug, Clone)]
+pub struct Command {
+    name: String,
+    // The name as the client sent it, for error messages that echo it back.
+    raw_name: String,
+    args: Vec<Vec<u8>>,
+}
+
+impl Command {
+    fn new(raw_name: String, args: Vec<Vec<u8>>) -> Command {
+        Command {
+            name: raw_name.to_uppercase(),
+            raw_name,
+            args,
+        }
+    }
+
+    // The first RESP array element is the command name, the rest are taken
+    // verbatim as arguments.
+    fn from_elements(mut elements: Vec<Vec<u8>>) -> Command {
+        let raw_name = if elements.is_empty() {
+            String::new()
+        } else {
+            String::from_utf8_lossy(&elements.remove(0)).into_owned()
+        };
+        Command::new(raw_name, elements)
+    }
+}
+
+impl fmt::Display for Command {
+    /// Renders the command the way `redis-cli` quotes replies: every element is
+    /// double-quoted and non-printable bytes are escaped, e.g. `"SET" "k" "\x00"`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_quoted(f, self.name.as_bytes())?;
+        for arg in &self.args {
+            write!(f, " ")?;
+            write_quoted(f, arg)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_quoted(f: &mut fmt::Formatter<'_>, bytes: &[u8]) -> fmt::Result {
+    write!(f, "\"")?;
+    for &b in bytes {
+        match b {
+            b'\\' => write!(f, "\\\\")?,
+            b'"' => write!(f, "\\\"")?,
+            b'\n' => write!(f, "\\n")?,
+            b'\r' => write!(f, "\\r")?,
+            b'\t' => write!(f, "\\t")?,
+            0x07 => write!(f, "\\a")?,
+            0x08 => write!(f, "\\b")?,
+            0x20..=0x7e => write!(f, "{}", b as char)?,
+            _ => write!(f, "\\x{:02x}", b)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    // Runs commands through a Response over a loopback socket and reads back
+    // each reply, so MULTI state and blocking behave as for a real client.
+    struct Session {
+        resp: Response<'static>,
+        client: BufReader<TcpStream>,
+        kv_store: Arc<RwLock<KvStore>>,
+        server_info: Arc<RwLock<ServerInfo>>,
+    }
+
+    impl Session {
+        fn new() -> Session {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            // Leaked so the Response can borrow it for the rest of the test.
+            let server: &'static TcpStream = Box::leak(Box::new(listener.accept().unwrap().0));
+            Session {
+                resp: Response::new(server),
+                client: BufReader::new(client),
+                kv_store: Arc::new(RwLock::new(KvStore::new())),
+                server_info: Arc::new(RwLock::new(ServerInfo::new(
+                    String::new(),
+                    6379,
+                    ServerRole::Master("master"),
+                ))),
+            }
+        }
+
+        fn cmd(&mut self, args: &[&str]) -> String {
+            let args: Vec<&[u8]> = args.iter().map(|arg| arg.as_bytes()).collect();
+            String::from_utf8(self.cmd_bytes(&args)).unwrap()
+        }
+
+        fn cmd_bytes(&mut self, args: &[&[u8]]) -> Vec<u8> {
+            self.run(&command(args))
+        }
+
+        fn run(&mut self, command: &Command) -> Vec<u8> {
+            self.resp
+                .process_command(command, &self.kv_store, &self.server_info)
+                .unwrap();
+            let mut reply = Vec::new();
+            read_reply(&mut self.client, &mut reply);
+            reply
+        }
+    }
+
+    // Appends one whole reply, nested arrays included.
+    fn read_reply(reader: &mut impl BufRead, reply: &mut Vec<u8>) {
+        let start = reply.len();
+        reader.read_until(b'\n', reply).unwrap();
+        let line = std::str::from_utf8(&reply[start..]).unwrap().trim_end();
+        let (kind, len) = line.split_at(1);
+
+        match (kind, len.parse::<i64>()) {
+            ("$", Ok(len)) if len >= 0 => {
+                let mut payload = vec![0; len as usize + 2];
+                reader.read_exact(&mut payload).unwrap();
+                reply.extend_from_slice(&payload);
+            }
+            ("*", Ok(cnt)) => {
+                for _ in 0..cnt.max(0) {
+                    read_reply(reader, reply);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Feeds raw bytes to a Request the way a client would, then hangs up.
+    fn read_request(bytes: &[u8], max_bulk_len: usize) -> Result<Option<Command>, Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        client.write_all(bytes).unwrap();
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+
+        let mut request = Request::new(&server, max_bulk_len);
+        Ok(request.read_command()?.then_some(request.command))
+    }
+
+    fn resp_array(elements: &[&[u8]]) -> Vec<u8> {
+        let mut bytes = format!("*{}\r\n", elements.len()).into_bytes();
+        for element in elements {
+            bytes.extend_from_slice(format!("${}\r\n", element.len()).as_bytes());
+            bytes.extend_from_slice(element);
+            bytes.extend_from_slice(b"\r\n");
+        }
+        bytes
+    }
+
+    fn command(elements: &[&[u8]]) -> Command {
+        Command::from_elements(elements.iter().map(|element| element.to_vec()).collect())
+    }
+
+    #[test]
+    fn command_display_escapes_non_printable_bytes() {
+        let set = command(&[b"set", b"k", b"\x00"]);
+        assert_eq!(set.to_string(), r#""SET" "k" "\x00""#);
+
+        let quoted = command(&[b"ECHO", b"say \"hi\"\r\n\\\xff"]);
+        assert_eq!(quoted.to_string(), r#""ECHO" "say \"hi\"\r\n\\\xff""#);
+    }
+
+    #[test]
+    fn set_rejects_conflicting_and_unknown_options() {
+        let mut session = Session::new();
+        let syntax_error = "-ERR syntax error\r\n";
+
+        assert_eq!(
+            session.cmd(&["SET", "k", "v", "EX", "10", "PX", "10000"]),
+            syntax_error
+        );
+        assert_eq!(session.cmd(&["SET", "k", "v", "NX", "XX"]), syntax_error);
+        assert_eq!(
+            session.cmd(&["SET", "k", "v", "EX", "10", "EX", "10"]),
+            syntax_error
+        );
+        assert_eq!(session.cmd(&["SET", "k", "v", "BOGUS"]), syntax_error);
+        assert_eq!(session.cmd(&["SET", "k", "v", "EX"]), syntax_error);
+        assert_eq!(session.cmd(&["EXISTS", "k"]), ":0\r\n");
+    }
+
+    #[test]
+    fn set_options_are_order_independent() {
+        let mut session = Session::new();
+
+        assert_eq!(
+            session.cmd(&["SET", "a", "1", "NX", "PX", "10000"]),
+            "+OK\r\n"
+        );
+        assert_eq!(
+            session.cmd(&["SET", "b", "1", "px", "10000", "nx"]),
+            "+OK\r\n"
+        );
+        assert_eq!(
+            session.cmd(&["SET", "a", "2", "GET", "XX", "KEEPTTL"]),
+            "$1\r\n1\r\n"
+        );
+        assert_eq!(
+            session.cmd(&["SET", "b", "2", "KEEPTTL", "XX", "GET"]),
+            "$1\r\n1\r\n"
+        );
+        for key in ["a", "b"] {
+            assert_eq!(session.cmd(&["GET", key]), "$1\r\n2\r\n");
+            assert_ne!(session.cmd(&["PTTL", key]), ":-1\r\n");
+        }
+    }
+
+    #[test]
+    fn binary_value_round_trips_through_set_and_get() {
+        let value: &[u8] = b"line1\r\nline2 with spaces\0\0end";
+        let set = read_request(
+            &resp_array(&[b"SET", b"bin", value]),
+            DEFAULT_PROTO_MAX_BULK_LEN,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(set.args, [b"bin".to_vec(), value.to_vec()]);
+
+        let mut session = Session::new();
+        assert_eq!(session.run(&set), b"+OK\r\n");
+        let get = read_request(&resp_array(&[b"GET", b"bin"]), DEFAULT_PROTO_MAX_BULK_LEN)
+            .unwrap()
+            .unwrap();
+        assert_eq!(session.run(&get), [b"$29\r\n", value, b"\r\n"].concat());
+    }
+
+    #[test]
+    fn elements_keep_spaces_and_resp_lookalikes() {
+        let elements: [&[u8]; 6] = [
+            b"set",
+            b"greeting",
+            b"hello world",
+            b"*2",
+            b"$3\r\n",
+            b"+OK\r\n",
+        ];
+        let command = read_request(&resp_array(&elements), DEFAULT_PROTO_MAX_BULK_LEN)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(command.name, "SET");
+        let expected: Vec<Vec<u8>> = elements[1..].iter().map(|e| e.to_vec()).collect();
+        assert_eq!(command.args, expected);
+    }
+
+    #[test]
+    fn multi_word_value_is_stored_whole() {
+        let mut session = Session::new();
+        assert_eq!(session.cmd(&["SET", "greeting", "hello world"]), "+OK\r\n");
+        assert_eq!(session.cmd(&["GET", "greeting"]), "$11\r\nhello world\r\n");
+    }
+
+    #[test]
+    fn huge_element_count_is_refused_before_allocating() {
+        let err = read_request(b"*100000000000\r\n", DEFAULT_PROTO_MAX_BULK_LEN).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR Protocol error: invalid multibulk length"
+        );
+        assert!(err.downcast_ref::<ProtocolError>().is_some());
+    }
+
+    #[test]
+    fn del_counts_only_keys_it_removed() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "a", "1"]);
+        session.cmd(&["SET", "c", "3"]);
+
+        assert_eq!(session.cmd(&["DEL", "a", "b", "c"]), ":2\r\n");
+        assert_eq!(session.cmd(&["EXISTS", "a", "c"]), ":0\r\n");
+        assert_eq!(session.cmd(&["DEL", "a"]), ":0\r\n");
+        assert_eq!(
+            session.cmd(&["DEL"]),
+            "-ERR wrong number of arguments for 'del' command\r\n"
+        );
+    }
+
+    #[test]
+    fn del_does_not_count_an_expired_key() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "k", "v", "PX", "10"]);
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(session.cmd(&["DEL", "k"]), ":0\r\n");
+    }
+
+    #[test]
+    fn del_runs_inside_a_transaction() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "a", "1"]);
+
+        assert_eq!(session.cmd(&["MULTI"]), "+OK\r\n");
+        assert_eq!(session.cmd(&["DEL", "a", "b"]), "+QUEUED\r\n");
+        assert_eq!(session.cmd(&["EXEC"]), "*1\r\n:1\r\n");
+        assert_eq!(session.cmd(&["GET", "a"]), "$-1\r\n");
+    }
+
+    #[test]
+    fn subscribe_inside_multi_aborts_the_transaction() {
+        let mut session = Session::new();
+
+        assert_eq!(session.cmd(&["MULTI"]), "+OK\r\n");
+        assert_eq!(session.cmd(&["SET", "a", "1"]), "+QUEUED\r\n");
+        assert_eq!(
+            session.cmd(&["SUBSCRIBE", "chan"]),
+            "-ERR SUBSCRIBE is not allowed in transactions\r\n"
+        );
+        assert_eq!(
+            session.cmd(&["EXEC"]),
+            "-EXECABORT Transaction discarded because of previous errors.\r\n"
+        );
+        assert_eq!(session.cmd(&["GET", "a"]), "$-1\r\n");
+    }
+
+    #[test]
+    fn discard_clears_a_pending_abort() {
+        let mut session = Session::new();
+
+        assert_eq!(session.cmd(&["MULTI"]), "+OK\r\n");
+        session.cmd(&["SUBSCRIBE", "chan"]);
+        assert_eq!(session.cmd(&["DISCARD"]), "+OK\r\n");
+
+        assert_eq!(session.cmd(&["MULTI"]), "+OK\r\n");
+        assert_eq!(session.cmd(&["SET", "a", "1"]), "+QUEUED\r\n");
+        assert_eq!(session.cmd(&["EXEC"]), "*1\r\n+OK\r\n");
+    }
+
+    #[test]
+    fn wrongtype_inside_exec_does_not_stop_the_rest() {
+        let mut session = Session::new();
+
+        assert_eq!(session.cmd(&["MULTI"]), "+OK\r\n");
+        assert_eq!(session.cmd(&["SET", "k", "v"]), "+QUEUED\r\n");
+        assert_eq!(session.cmd(&["LPUSH", "k", "x"]), "+QUEUED\r\n");
+        assert_eq!(session.cmd(&["GET", "k"]), "+QUEUED\r\n");
+        assert_eq!(
+            session.cmd(&["EXEC"]),
+            "*3\r\n+OK\r\n\
+             -WRONGTYPE Operation against a key holding the wrong kind of value\r\n\
+             $1\r\nv\r\n"
+        );
+    }
+
+    #[test]
+    fn exists_counts_repeated_keys() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "foo", "1"]);
+
+        assert_eq!(session.cmd(&["EXISTS", "foo", "foo"]), ":2\r\n");
+        assert_eq!(session.cmd(&["EXISTS", "foo", "foo", "foo"]), ":3\r\n");
+    }
+
+    #[test]
+    fn exists_skips_missing_and_expired_keys() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "present", "1"]);
+        session.cmd(&["SET", "expired", "1", "PX", "10"]);
+        thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(
+            session.cmd(&["EXISTS", "present", "absent", "expired"]),
+            ":1\r\n"
+        );
+        assert_eq!(
+            session.cmd(&["EXISTS"]),
+            "-ERR wrong number of arguments for 'exists' command\r\n"
+        );
+    }
+
+    #[test]
+    fn keys_matches_non_utf8_keys() {
+        let mut session = Session::new();
+        let key: &[u8] = b"\xff\xfe\x00k";
+        session.cmd_bytes(&[b"SET", key, b"v"]);
+
+        let expected = [b"*1\r\n$4\r\n", key, b"\r\n"].concat();
+        assert_eq!(session.cmd_bytes(&[b"KEYS", b"*"]), expected);
+        assert_eq!(session.cmd_bytes(&[b"KEYS", b"\xff?\x00*"]), expected);
+        assert_eq!(session.cmd_bytes(&[b"KEYS", b"\xfe*"]), b"*0\r\n");
+    }
+
+    #[test]
+    fn flushdb_and_flushall_clear_the_store() {
+        let mut session = Session::new();
+
+        for flush in [
+            &["FLUSHDB"][..],
+            &["FLUSHDB", "ASYNC"],
+            &["FLUSHALL", "sync"],
+            &["FLUSHALL"],
+        ] {
+            session.cmd(&["SET", "a", "1"]);
+            session.cmd(&["RPUSH", "l", "x"]);
+            assert_eq!(session.cmd(flush), "+OK\r\n");
+            assert_eq!(session.cmd(&["DBSIZE"]), ":0\r\n");
+        }
+    }
+
+    #[test]
+    fn flush_rejects_extra_tokens() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "a", "1"]);
+
+        for flush in [&["FLUSHDB", "now"][..], &["FLUSHALL", "ASYNC", "SYNC"]] {
+            assert_eq!(session.cmd(flush), "-ERR syntax error\r\n");
+        }
+        assert_eq!(session.cmd(&["DBSIZE"]), ":1\r\n");
+    }
+
+    #[test]
+    fn expire_then_get_after_the_ttl_passes() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "k", "v"]);
+
+        assert_eq!(session.cmd(&["PEXPIRE", "k", "20"]), ":1\r\n");
+        assert_eq!(session.cmd(&["GET", "k"]), "$1\r\nv\r\n");
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(session.cmd(&["GET", "k"]), "$-1\r\n");
+    }
+
+    #[test]
+    fn expire_sets_a_ttl_in_seconds() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "k", "v"]);
+
+        assert_eq!(session.cmd(&["EXPIRE", "k", "100"]), ":1\r\n");
+        assert_eq!(session.cmd(&["TTL", "k"]), ":100\r\n");
+        assert_eq!(session.cmd(&["EXPIRE", "missing", "100"]), ":0\r\n");
+    }
+
+    #[test]
+    fn expire_with_a_ttl_of_zero_or_less_deletes_the_key() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "a", "v"]);
+        session.cmd(&["SET", "b", "v"]);
+
+        assert_eq!(session.cmd(&["EXPIRE", "a", "0"]), ":1\r\n");
+        assert_eq!(session.cmd(&["PEXPIRE", "b", "-5"]), ":1\r\n");
+        assert_eq!(session.cmd(&["EXISTS", "a", "b"]), ":0\r\n");
+    }
+
+    #[test]
+    fn expire_rejects_a_non_integer_ttl() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "k", "v"]);
+
+        let not_an_integer = "-ERR value is not an integer or out of range\r\n";
+        assert_eq!(session.cmd(&["EXPIRE", "k", "1.5"]), not_an_integer);
+        assert_eq!(session.cmd(&["PEXPIRE", "k", "soon"]), not_an_integer);
+        assert_eq!(session.cmd(&["TTL", "k"]), ":-1\r\n");
+    }
+
+    #[test]
+    fn ttl_reports_missing_persistent_and_expiring_keys() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "plain", "v"]);
+        session.cmd(&["SET", "expiring", "v", "EX", "100"]);
+
+        assert_eq!(session.cmd(&["TTL", "missing"]), ":-2\r\n");
+        assert_eq!(session.cmd(&["PTTL", "missing"]), ":-2\r\n");
+        assert_eq!(session.cmd(&["TTL", "plain"]), ":-1\r\n");
+        assert_eq!(session.cmd(&["PTTL", "plain"]), ":-1\r\n");
+        assert_eq!(session.cmd(&["TTL", "expiring"]), ":100\r\n");
+    }
+
+    #[test]
+    fn ttl_rounds_to_the_nearest_second() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "long", "v", "PX", "1700"]);
+        session.cmd(&["SET", "short", "v", "PX", "400"]);
+
+        assert_eq!(session.cmd(&["TTL", "long"]), ":2\r\n");
+        assert_eq!(session.cmd(&["TTL", "short"]), ":0\r\n");
+        for (key, set_mills) in [("long", 1700), ("short", 400)] {
+            let pttl: i64 = session.cmd(&["PTTL", key])[1..].trim().parse().unwrap();
+            assert!((set_mills - 50..=set_mills).contains(&pttl), "{}", pttl);
+        }
+    }
+
+    #[test]
+    fn pttl_decreases_until_the_key_is_gone() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "k", "v", "PX", "100"]);
+
+        let pttl = |session: &mut Session| -> i64 {
+            let reply = session.cmd(&["PTTL", "k"]);
+            reply.trim_start_matches(':').trim_end().parse().unwrap()
+        };
+        let first = pttl(&mut session);
+        assert!(first > 0 && first <= 100);
+        thread::sleep(Duration::from_millis(30));
+        let second = pttl(&mut session);
+        assert!(second > 0 && second < first);
+
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(session.cmd(&["PTTL", "k"]), ":-2\r\n");
+    }
+
+    #[test]
+    fn persist_removes_a_ttl() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "k", "v", "PX", "10000"]);
+
+        assert_eq!(session.cmd(&["PERSIST", "k"]), ":1\r\n");
+        assert_eq!(session.cmd(&["TTL", "k"]), ":-1\r\n");
+        assert_eq!(session.cmd(&["PERSIST", "k"]), ":0\r\n");
+        assert_eq!(session.cmd(&["PERSIST", "missing"]), ":0\r\n");
+    }
+
+    #[test]
+    fn set_nx_only_sets_a_missing_key() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "k", "old"]);
+
+        assert_eq!(session.cmd(&["SET", "k", "new", "NX"]), "$-1\r\n");
+        assert_eq!(session.cmd(&["GET", "k"]), "$3\r\nold\r\n");
+        assert_eq!(session.cmd(&["SET", "fresh", "v", "NX"]), "+OK\r\n");
+        assert_eq!(session.cmd(&["GET", "fresh"]), "$1\r\nv\r\n");
+    }
+
+    #[test]
+    fn set_nx_treats_an_expired_key_as_missing() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "k", "old", "PX", "10"]);
+        thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(session.cmd(&["SET", "k", "new", "NX"]), "+OK\r\n");
+        assert_eq!(session.cmd(&["GET", "k"]), "$3\r\nnew\r\n");
+    }
+
+    #[test]
+    fn set_xx_only_sets_an_existing_key() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "k", "old"]);
+
+        assert_eq!(session.cmd(&["SET", "k", "new", "XX"]), "+OK\r\n");
+        assert_eq!(session.cmd(&["GET", "k"]), "$3\r\nnew\r\n");
+        assert_eq!(session.cmd(&["SET", "missing", "v", "XX"]), "$-1\r\n");
+        assert_eq!(session.cmd(&["EXISTS", "missing"]), ":0\r\n");
+    }
+
+    #[test]
+    fn set_keepttl_keeps_the_existing_expiry() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "k", "v", "PX", "10000"]);
+
+        assert_eq!(session.cmd(&["SET", "k", "v2", "KEEPTTL"]), "+OK\r\n");
+        assert_eq!(session.cmd(&["GET", "k"]), "$2\r\nv2\r\n");
+        assert_eq!(session.cmd(&["TTL", "k"]), ":10\r\n");
+    }
+
+    #[test]
+    fn plain_set_clears_the_expiry() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "k", "v", "PX", "10000"]);
+
+        assert_eq!(session.cmd(&["SET", "k", "v2"]), "+OK\r\n");
+        assert_eq!(session.cmd(&["TTL", "k"]), ":-1\r\n");
+    }
+
+    #[test]
+    fn set_keepttl_conflicts_with_an_expiry() {
+        let mut session = Session::new();
+        assert_eq!(
+            session.cmd(&["SET", "k", "v", "KEEPTTL", "EX", "10"]),
+            "-ERR syntax error\r\n"
+        );
+    }
+
+    #[test]
+    fn set_get_returns_the_replaced_value() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "k", "old"]);
+
+        assert_eq!(session.cmd(&["SET", "k", "new", "GET"]), "$3\r\nold\r\n");
+        assert_eq!(session.cmd(&["GET", "k"]), "$3\r\nnew\r\n");
+    }
+
+    #[test]
+    fn set_get_on_a_missing_key_replies_null_and_still_sets() {
+        let mut session = Session::new();
+
+        assert_eq!(session.cmd(&["SET", "k", "new", "GET"]), "$-1\r\n");
+        assert_eq!(session.cmd(&["GET", "k"]), "$3\r\nnew\r\n");
+    }
+
+    #[test]
+    fn set_get_against_a_list_is_wrongtype() {
+        let mut session = Session::new();
+        session.cmd(&["RPUSH", "list", "a"]);
+
+        assert_eq!(
+            session.cmd(&["SET", "list", "v", "GET"]),
+            "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+        assert_eq!(session.cmd(&["TYPE", "list"]), "+list\r\n");
+    }
+
+    fn unix_millis_from_now(offset_mills: i64) -> String {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap();
+        (now.as_millis() as i64 + offset_mills).to_string()
+    }
+
+    #[test]
+    fn expireat_in_the_future_sets_a_ttl() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "a", "v"]);
+        session.cmd(&["SET", "b", "v"]);
+
+        let in_100s = unix_millis_from_now(100_000);
+        assert_eq!(session.cmd(&["PEXPIREAT", "a", &in_100s]), ":1\r\n");
+        let in_100s_secs = (in_100s.parse::<i64>().unwrap() / 1000 + 1).to_string();
+        assert_eq!(session.cmd(&["EXPIREAT", "b", &in_100s_secs]), ":1\r\n");
+        for key in ["a", "b"] {
+            let ttl = session.cmd(&["TTL", key]);
+            assert!(ttl == ":100\r\n" || ttl == ":101\r\n", "{}", ttl);
+        }
+    }
+
+    #[test]
+    fn expireat_in_the_past_deletes_the_key() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "a", "v"]);
+        session.cmd(&["SET", "b", "v"]);
+
+        assert_eq!(
+            session.cmd(&["PEXPIREAT", "a", &unix_millis_from_now(-1000)]),
+            ":1\r\n"
+        );
+        assert_eq!(session.cmd(&["EXPIREAT", "b", "1"]), ":1\r\n");
+        assert_eq!(session.cmd(&["EXISTS", "a", "b"]), ":0\r\n");
+        assert_eq!(session.cmd(&["EXPIREAT", "missing", "1"]), ":0\r\n");
+    }
+
+    #[test]
+    fn set_pxat_expires_at_the_deadline() {
+        let mut session = Session::new();
+        let deadline = unix_millis_from_now(50);
+
+        assert_eq!(
+            session.cmd(&["SET", "k", "v", "PXAT", &deadline]),
+            "+OK\r\n"
+        );
+        assert_eq!(session.cmd(&["GET", "k"]), "$1\r\nv\r\n");
+        thread::sleep(Duration::from_millis(70));
+        assert_eq!(session.cmd(&["GET", "k"]), "$-1\r\n");
+    }
+
+    #[test]
+    fn set_pxat_in_the_past_leaves_no_key() {
+        let mut session = Session::new();
+        let deadline = unix_millis_from_now(-1000);
+
+        assert_eq!(
+            session.cmd(&["SET", "k", "v", "PXAT", &deadline]),
+            "+OK\r\n"
+        );
+        assert_eq!(session.cmd(&["GET", "k"]), "$-1\r\n");
+        assert_eq!(session.cmd(&["EXISTS", "k"]), ":0\r\n");
+    }
+
+    #[test]
+    fn set_exat_sets_a_ttl() {
+        let mut session = Session::new();
+        let in_100s =
+            (unix_millis_from_now(100_000).parse::<i64>().unwrap() / 1000 + 1).to_string();
+
+        assert_eq!(session.cmd(&["SET", "k", "v", "EXAT", &in_100s]), "+OK\r\n");
+        let ttl = session.cmd(&["TTL", "k"]);
+        assert!(ttl == ":100\r\n" || ttl == ":101\r\n", "{}", ttl);
+    }
+
+    #[test]
+    fn set_absolute_expiry_conflicts_with_any_other() {
+        let mut session = Session::new();
+        let deadline = unix_millis_from_now(10_000);
+        let syntax_error = "-ERR syntax error\r\n";
+
+        assert_eq!(
+            session.cmd(&["SET", "k", "v", "PXAT", &deadline, "EX", "10"]),
+            syntax_error
+        );
+        assert_eq!(
+            session.cmd(&["SET", "k", "v", "PX", "10", "EXAT", "1"]),
+            syntax_error
+        );
+        assert_eq!(
+            session.cmd(&["SET", "k", "v", "EXAT", "1", "PXAT", &deadline]),
+            syntax_error
+        );
+        assert_eq!(session.cmd(&["EXISTS", "k"]), ":0\r\n");
+    }
+
+    #[test]
+    fn incr_family_adjusts_the_counter() {
+        let mut session = Session::new();
+
+        assert_eq!(session.cmd(&["INCR", "n"]), ":1\r\n");
+        assert_eq!(session.cmd(&["INCRBY", "n", "10"]), ":11\r\n");
+        assert_eq!(session.cmd(&["DECR", "n"]), ":10\r\n");
+        assert_eq!(session.cmd(&["DECRBY", "n", "15"]), ":-5\r\n");
+        assert_eq!(session.cmd(&["INCRBY", "n", "-5"]), ":-10\r\n");
+        assert_eq!(session.cmd(&["GET", "n"]), "$3\r\n-10\r\n");
+        assert_eq!(session.cmd(&["DECR", "fresh"]), ":-1\r\n");
+    }
+
+    #[test]
+    fn incr_family_refuses_to_overflow() {
+        let mut session = Session::new();
+        let overflow = "-ERR increment or decrement would overflow\r\n";
+        let max = i64::MAX.to_string();
+        let min = i64::MIN.to_string();
+
+        session.cmd(&["SET", "n", &(i64::MAX - 1).to_string()]);
+        assert_eq!(session.cmd(&["INCR", "n"]), format!(":{}\r\n", max));
+        assert_eq!(session.cmd(&["INCR", "n"]), overflow);
+        assert_eq!(session.cmd(&["INCRBY", "n", "1"]), overflow);
+        assert_eq!(
+            session.cmd(&["GET", "n"]),
+            format!("${}\r\n{}\r\n", max.len(), max)
+        );
+
+        session.cmd(&["SET", "n", &(i64::MIN + 1).to_string()]);
+        assert_eq!(session.cmd(&["DECR", "n"]), format!(":{}\r\n", min));
+        assert_eq!(session.cmd(&["DECR", "n"]), overflow);
+        assert_eq!(session.cmd(&["DECRBY", "n", "1"]), overflow);
+        // Negating i64::MIN alone would already overflow, Redis says so.
+        session.cmd(&["SET", "m", "0"]);
+        assert_eq!(
+            session.cmd(&["DECRBY", "m", &min]),
+            "-ERR decrement would overflow\r\n"
+        );
+    }
+
+    #[test]
+    fn incr_family_rejects_non_integers() {
+        let mut session = Session::new();
+        let not_an_integer = "-ERR value is not an integer or out of range\r\n";
+
+        assert_eq!(session.cmd(&["INCRBY", "n", "1.5"]), not_an_integer);
+        assert_eq!(session.cmd(&["DECRBY", "n", "x"]), not_an_integer);
+        session.cmd(&["SET", "s", "abc"]);
+        assert_eq!(session.cmd(&["INCR", "s"]), not_an_integer);
+        assert_eq!(session.cmd(&["EXISTS", "n"]), ":0\r\n");
+    }
+
+    #[test]
+    fn incr_family_keeps_the_ttl() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "n", "5", "PX", "10000"]);
+
+        assert_eq!(session.cmd(&["INCR", "n"]), ":6\r\n");
+        assert_eq!(session.cmd(&["INCRBY", "n", "4"]), ":10\r\n");
+        assert_eq!(session.cmd(&["DECR", "n"]), ":9\r\n");
+        assert_eq!(session.cmd(&["DECRBY", "n", "2"]), ":7\r\n");
+        assert_eq!(session.cmd(&["TTL", "n"]), ":10\r\n");
+    }
+
+    #[test]
+    fn incrbyfloat_refuses_an_infinite_result() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "n", "1.5"]);
+
+        for incr in ["inf", "-inf"] {
+            assert_eq!(
+                session.cmd(&["INCRBYFLOAT", "n", incr]),
+                "-ERR increment would produce NaN or Infinity\r\n"
+            );
+        }
+        assert_eq!(session.cmd(&["GET", "n"]), "$3\r\n1.5\r\n");
+
+        session.cmd(&["SET", "big", "1e308"]);
+        assert_eq!(
+            session.cmd(&["INCRBYFLOAT", "big", "1e308"]),
+            "-ERR increment would produce NaN or Infinity\r\n"
+        );
+        assert_eq!(session.cmd(&["GET", "big"]), "$5\r\n1e308\r\n");
+    }
+
+    #[test]
+    fn incrbyfloat_keeps_the_ttl() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "n", "1", "PX", "10000"]);
+
+        assert_eq!(session.cmd(&["INCRBYFLOAT", "n", "1.5"]), "$3\r\n2.5\r\n");
+        assert_eq!(session.cmd(&["TTL", "n"]), ":10\r\n");
+    }
+
+    #[test]
+    fn incrbyfloat_takes_exactly_two_arguments() {
+        let mut session = Session::new();
+
+        assert_eq!(
+            session.cmd(&["INCRBYFLOAT", "n", "1", "2"]),
+            "-ERR wrong number of arguments for 'incrbyfloat' command\r\n"
+        );
+        assert_eq!(session.cmd(&["EXISTS", "n"]), ":0\r\n");
+    }
+
+    #[test]
+    fn append_keeps_the_ttl() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "k", "ab", "PX", "10000"]);
+
+        assert_eq!(session.cmd(&["APPEND", "k", "cd"]), ":4\r\n");
+        assert_eq!(session.cmd(&["GET", "k"]), "$4\r\nabcd\r\n");
+        let pttl: i64 = session.cmd(&["PTTL", "k"])[1..].trim().parse().unwrap();
+        assert!((9_000..=10_000).contains(&pttl), "{}", pttl);
+    }
+
+    #[test]
+    fn append_to_a_missing_key_creates_a_persistent_string() {
+        let mut session = Session::new();
+
+        assert_eq!(session.cmd(&["APPEND", "k", "abc"]), ":3\r\n");
+        assert_eq!(session.cmd(&["GET", "k"]), "$3\r\nabc\r\n");
+        assert_eq!(session.cmd(&["TTL", "k"]), ":-1\r\n");
+    }
+
+    #[test]
+    fn append_takes_exactly_two_arguments() {
+        let mut session = Session::new();
+
+        assert_eq!(
+            session.cmd(&["APPEND", "k", "a", "b"]),
+            "-ERR wrong number of arguments for 'append' command\r\n"
+        );
+        assert_eq!(session.cmd(&["EXISTS", "k"]), ":0\r\n");
+    }
+
+    #[test]
+    fn strlen_counts_bytes() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "empty", ""]);
+        session.cmd(&["SET", "ascii", "hello"]);
+        session.cmd(&["SET", "multibyte", "héllo→"]);
+
+        assert_eq!(session.cmd(&["STRLEN", "empty"]), ":0\r\n");
+        assert_eq!(session.cmd(&["STRLEN", "ascii"]), ":5\r\n");
+        assert_eq!(session.cmd(&["STRLEN", "multibyte"]), ":9\r\n");
+        assert_eq!(session.cmd(&["STRLEN", "missing"]), ":0\r\n");
+    }
+
+    #[test]
+    fn strlen_against_a_list_is_wrongtype() {
+        let mut session = Session::new();
+        session.cmd(&["LPUSH", "list", "a"]);
+
+        assert_eq!(
+            session.cmd(&["STRLEN", "list"]),
+            "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+    }
+
+    #[test]
+    fn getset_returns_the_old_value_and_clears_the_ttl() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "k", "old", "PX", "10000"]);
+
+        assert_eq!(session.cmd(&["GETSET", "k", "new"]), "$3\r\nold\r\n");
+        assert_eq!(session.cmd(&["GET", "k"]), "$3\r\nnew\r\n");
+        assert_eq!(session.cmd(&["TTL", "k"]), ":-1\r\n");
+    }
+
+    #[test]
+    fn getset_on_a_missing_key_replies_null_and_sets() {
+        let mut session = Session::new();
+
+        assert_eq!(session.cmd(&["GETSET", "k", "new"]), "$-1\r\n");
+        assert_eq!(session.cmd(&["GET", "k"]), "$3\r\nnew\r\n");
+    }
+
+    #[test]
+    fn setnx_only_sets_a_missing_or_expired_key() {
+        let mut session = Session::new();
+
+        assert_eq!(session.cmd(&["SETNX", "k", "first"]), ":1\r\n");
+        assert_eq!(session.cmd(&["SETNX", "k", "second"]), ":0\r\n");
+        assert_eq!(session.cmd(&["GET", "k"]), "$5\r\nfirst\r\n");
+
+        session.cmd(&["SET", "gone", "v", "PX", "10"]);
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(session.cmd(&["SETNX", "gone", "again"]), ":1\r\n");
+        assert_eq!(session.cmd(&["GET", "gone"]), "$5\r\nagain\r\n");
+    }
+
+    #[test]
+    fn getrange_resolves_negative_and_out_of_range_offsets() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "k", "This is a string"]);
+
+        assert_eq!(session.cmd(&["GETRANGE", "k", "0", "3"]), "$4\r\nThis\r\n");
+        assert_eq!(session.cmd(&["GETRANGE", "k", "-3", "-1"]), "$3\r\ning\r\n");
+        assert_eq!(
+            session.cmd(&["GETRANGE", "k", "0", "-1"]),
+            "$16\r\nThis is a string\r\n"
+        );
+        assert_eq!(
+            session.cmd(&["GETRANGE", "k", "-100", "3"]),
+            "$4\r\nThis\r\n"
+        );
+        assert_eq!(
+            session.cmd(&["GETRANGE", "k", "10", "100"]),
+            "$6\r\nstring\r\n"
+        );
+        assert_eq!(session.cmd(&["GETRANGE", "k", "5", "2"]), "$0\r\n\r\n");
+        assert_eq!(
+            session.cmd(&["GETRANGE", "missing", "0", "-1"]),
+            "$0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn setrange_overwrites_in_place() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "k", "Hello World"]);
+
+        assert_eq!(session.cmd(&["SETRANGE", "k", "6", "Redis"]), ":11\r\n");
+        assert_eq!(session.cmd(&["GET", "k"]), "$11\r\nHello Redis\r\n");
+        assert_eq!(session.cmd(&["SETRANGE", "k", "6", "Redis!!"]), ":13\r\n");
+        assert_eq!(session.cmd(&["GET", "k"]), "$13\r\nHello Redis!!\r\n");
+    }
+
+    #[test]
+    fn setrange_zero_pads_past_the_end() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "k", "ab"]);
+
+        assert_eq!(session.cmd(&["SETRANGE", "k", "4", "cd"]), ":6\r\n");
+        assert_eq!(session.cmd_bytes(&[b"GET", b"k"]), b"$6\r\nab\0\0cd\r\n");
+        assert_eq!(session.cmd(&["SETRANGE", "new", "3", "x"]), ":4\r\n");
+        assert_eq!(session.cmd_bytes(&[b"GET", b"new"]), b"$4\r\n\0\0\0x\r\n");
+    }
+
+    #[test]
+    fn setrange_with_an_empty_value_does_not_create_the_key() {
+        let mut session = Session::new();
+
+        for offset in ["0", "5"] {
+            assert_eq!(session.cmd(&["SETRANGE", "k", offset, ""]), ":0\r\n");
+            assert_eq!(session.cmd(&["EXISTS", "k"]), ":0\r\n");
+        }
+    }
+
+    #[test]
+    fn setrange_rejects_a_negative_offset() {
+        let mut session = Session::new();
+        assert_eq!(
+            session.cmd(&["SETRANGE", "k", "-1", "x"]),
+            "-ERR offset is out of range\r\n"
+        );
+    }
+
+    #[test]
+    fn setrange_and_append_refuse_to_grow_past_proto_max_bulk_len() {
+        let mut session = Session::new();
+        let too_big = "-ERR string exceeds maximum allowed size (proto-max-bulk-len)\r\n";
+
+        assert_eq!(session.cmd(&["SETRANGE", "k", "536870912", "x"]), too_big);
+        assert_eq!(session.cmd(&["EXISTS", "k"]), ":0\r\n");
+
+        session.server_info.write().unwrap().proto_max_bulk_len = 4;
+        assert_eq!(session.cmd(&["APPEND", "k", "abcd"]), ":4\r\n");
+        assert_eq!(session.cmd(&["APPEND", "k", "e"]), too_big);
+        assert_eq!(session.cmd(&["GET", "k"]), "$4\r\nabcd\r\n");
+    }
+
+    #[test]
+    fn type_names_each_kind_of_value() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "s", "v"]);
+        session.cmd(&["RPUSH", "l", "a"]);
+        session.cmd(&["HSET", "h", "f", "v"]);
+
+        assert_eq!(session.cmd(&["TYPE", "s"]), "+string\r\n");
+        assert_eq!(session.cmd(&["TYPE", "l"]), "+list\r\n");
+        assert_eq!(session.cmd(&["TYPE", "h"]), "+hash\r\n");
+        assert_eq!(session.cmd(&["TYPE", "missing"]), "+none\r\n");
+    }
+
+    #[test]
+    fn string_commands_against_a_list_are_wrongtype() {
+        let mut session = Session::new();
+        session.cmd(&["RPUSH", "l", "a"]);
+        let wrongtype = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+
+        assert_eq!(session.cmd(&["GET", "l"]), wrongtype);
+        assert_eq!(session.cmd(&["INCR", "l"]), wrongtype);
+        assert_eq!(session.cmd(&["APPEND", "l", "x"]), wrongtype);
+        // A plain SET replaces the value whatever its type.
+        assert_eq!(session.cmd(&["SET", "l", "v"]), "+OK\r\n");
+        assert_eq!(session.cmd(&["TYPE", "l"]), "+string\r\n");
+    }
+
+    #[test]
+    fn pushes_keep_their_order() {
+        let mut session = Session::new();
+
+        assert_eq!(session.cmd(&["RPUSH", "l", "b", "c"]), ":2\r\n");
+        assert_eq!(session.cmd(&["LPUSH", "l", "a", "z"]), ":4\r\n");
+        assert_eq!(
+            session.cmd(&["LRANGE", "l", "0", "-1"]),
+            "*4\r\n$1\r\nz\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\nc\r\n"
+        );
+    }
+
+    #[test]
+    fn lrange_resolves_negative_and_out_of_range_bounds() {
+        let mut session = Session::new();
+        session.cmd(&["RPUSH", "l", "a", "b", "c", "d"]);
+
+        assert_eq!(
+            session.cmd(&["LRANGE", "l", "-2", "-1"]),
+            "*2\r\n$1\r\nc\r\n$1\r\nd\r\n"
+        );
+        assert_eq!(
+            session.cmd(&["LRANGE", "l", "-100", "0"]),
+            "*1\r\n$1\r\na\r\n"
+        );
+        assert_eq!(
+            session.cmd(&["LRANGE", "l", "2", "100"]),
+            "*2\r\n$1\r\nc\r\n$1\r\nd\r\n"
+        );
+        assert_eq!(session.cmd(&["LRANGE", "l", "3", "1"]), "*0\r\n");
+        assert_eq!(session.cmd(&["LRANGE", "missing", "0", "-1"]), "*0\r\n");
+    }
+
+    #[test]
+    fn list_commands_against_a_string_are_wrongtype() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "s", "v"]);
+        let wrongtype = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+
+        assert_eq!(session.cmd(&["RPUSH", "s", "a"]), wrongtype);
+        assert_eq!(session.cmd(&["LPUSH", "s", "a"]), wrongtype);
+        assert_eq!(session.cmd(&["LRANGE", "s", "0", "-1"]), wrongtype);
+        assert_eq!(session.cmd(&["GET", "s"]), "$1\r\nv\r\n");
+    }
+
+    #[test]
+    fn pop_without_a_count_returns_one_element() {
+        let mut session = Session::new();
+        session.cmd(&["RPUSH", "l", "a", "b", "c"]);
+
+        assert_eq!(session.cmd(&["LPOP", "l"]), "$1\r\na\r\n");
+        assert_eq!(session.cmd(&["RPOP", "l"]), "$1\r\nc\r\n");
+        assert_eq!(session.cmd(&["LPOP", "missing"]), "$-1\r\n");
+    }
+
+    #[test]
+    fn pop_with_a_count_returns_at_most_what_is_there() {
+        let mut session = Session::new();
+        session.cmd(&["RPUSH", "l", "a", "b", "c"]);
+
+        assert_eq!(
+            session.cmd(&["RPOP", "l", "2"]),
+            "*2\r\n$1\r\nc\r\n$1\r\nb\r\n"
+        );
+        assert_eq!(session.cmd(&["LPOP", "l", "10"]), "*1\r\n$1\r\na\r\n");
+        assert_eq!(session.cmd(&["LPOP", "l", "10"]), "*-1\r\n");
+        assert_eq!(
+            session.cmd(&["LPOP", "l", "-1"]),
+            "-ERR value is out of range, must be positive\r\n"
+        );
+    }
+
+    #[test]
+    fn popping_the_last_element_deletes_the_key() {
+        let mut session = Session::new();
+        session.cmd(&["RPUSH", "l", "only"]);
+
+        assert_eq!(session.cmd(&["LPOP", "l"]), "$4\r\nonly\r\n");
+        assert_eq!(session.cmd(&["EXISTS", "l"]), ":0\r\n");
+        assert_eq!(session.cmd(&["TYPE", "l"]), "+none\r\n");
+    }
+
+    #[test]
+    fn bulk_length_over_the_limit_is_a_protocol_error() {
+        let err = read_request(b"*1\r\n$17\r\n", 16).unwrap_err();
+        assert_eq!(err.to_string(), "ERR Protocol error: invalid bulk length");
+        assert!(err.downcast_ref::<ProtocolError>().is_some());
+
+        let at_limit = read_request(&resp_array(&[&[b'x'; 16]]), 16)
+            .unwrap()
+            .unwrap();
+        assert_eq!(at_limit.name, "X".repeat(16));
+    }
+
+    #[test]
+    fn header_line_over_the_limit_is_a_protocol_error() {
+        let mut count_line = b"*".to_vec();
+        count_line.resize(MAX_HEADER_LEN + 10, b'1');
+        let err = read_request(&count_line, DEFAULT_PROTO_MAX_BULK_LEN).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR Protocol error: too big mbulk count string"
+        );
+
+        let mut bulk_line = b"*1\r\n$".to_vec();
+        bulk_line.resize(MAX_HEADER_LEN + 10, b'1');
+        let err = read_request(&bulk_line, DEFAULT_PROTO_MAX_BULK_LEN).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR Protocol error: too big bulk count string"
+        );
+    }
+
+    #[test]
+    fn llen_tracks_pushes_and_pops() {
+        let mut session = Session::new();
+
+        assert_eq!(session.cmd(&["LLEN", "l"]), ":0\r\n");
+        session.cmd(&["RPUSH", "l", "a", "b"]);
+        assert_eq!(session.cmd(&["LLEN", "l"]), ":2\r\n");
+        session.cmd(&["LPUSH", "l", "c"]);
+        assert_eq!(session.cmd(&["LLEN", "l"]), ":3\r\n");
+        session.cmd(&["LPOP", "l"]);
+        assert_eq!(session.cmd(&["LLEN", "l"]), ":2\r\n");
+        session.cmd(&["RPOP", "l", "5"]);
+        assert_eq!(session.cmd(&["LLEN", "l"]), ":0\r\n");
+    }
+
+    #[test]
+    fn llen_against_a_string_is_wrongtype() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "s", "v"]);
+
+        assert_eq!(
+            session.cmd(&["LLEN", "s"]),
+            "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+    }
+
+    #[test]
+    fn non_bulk_element_is_a_protocol_error() {
+        let err = read_request(b"*1\r\n:1\r\n", DEFAULT_PROTO_MAX_BULK_LEN).unwrap_err();
+        assert_eq!(err.to_string(), "ERR Protocol error: expected '$', got ':'");
+    }
+
+    #[test]
+    fn malformed_header_is_a_protocol_error() {
+        let cases: [(&[u8], &str); 6] = [
+            (b"\r\n", "expected '*', got '\\r'"),
+            ("é\r\n".as_bytes(), "expected '*', got 'é'"),
+            (b"PING\r\n", "expected '*', got 'P'"),
+            (b"*x\r\n", "invalid multibulk length"),
+            (b"*1\r\n$-1\r\n", "invalid bulk length"),
+            (b"*1\r\n$4\r\nPINGxx", "expected CRLF after bulk string"),
+        ];
+        for (bytes, message) in cases {
+            let err = read_request(bytes, DEFAULT_PROTO_MAX_BULK_LEN).unwrap_err();
+            assert!(err.downcast_ref::<ProtocolError>().is_some(), "{:?}", bytes);
+            assert_eq!(err.to_string(), format!("ERR Protocol error: {}", message));
+        }
+    }
+
+    #[test]
+    fn eof_between_commands_is_a_clean_disconnect() {
+        assert!(
+            read_request(b"", DEFAULT_PROTO_MAX_BULK_LEN)
+                .unwrap()
+                .is_none()
+        );
+        let truncated = read_request(b"*2\r\n$4\r\nECHO\r\n", DEFAULT_PROTO_MAX_BULK_LEN);
+        assert!(
+            truncated
+                .unwrap_err()
+                .downcast_ref::<ProtocolError>()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn empty_array_is_skipped_without_a_reply() {
+        let ping = read_request(
+            b"*0\r\n*0\r\n*1\r\n$4\r\nPING\r\n",
+            DEFAULT_PROTO_MAX_BULK_LEN,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(ping.name, "PING");
+        assert!(ping.args.is_empty());
+
+        assert!(
+            read_request(b"*0\r\n", DEFAULT_PROTO_MAX_BULK_LEN)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn zero_length_name_is_an_unknown_command() {
+        let command = read_request(b"*1\r\n$0\r\n\r\n", DEFAULT_PROTO_MAX_BULK_LEN)
+            .unwrap()
+            .unwrap();
+        assert_eq!(command.name, "");
+
+        let mut session = Session::new();
+        assert_eq!(
+            session.run(&command),
+            b"-ERR unknown command '', with args beginning with: \r\n"
+        );
+    }
+
+    #[test]
+    fn empty_element_is_kept_as_an_empty_argument() {
+        let echo = read_request(
+            b"*2\r\n$4\r\nECHO\r\n$0\r\n\r\n",
+            DEFAULT_PROTO_MAX_BULK_LEN,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(echo.args, [Vec::<u8>::new()]);
+
+        let mut session = Session::new();
+        assert_eq!(session.run(&echo), b"$0\r\n\r\n");
+    }
+
+    #[test]
+    fn hset_counts_only_new_fields() {
+        let mut session = Session::new();
+
+        assert_eq!(session.cmd(&["HSET", "h", "a", "1", "b", "2"]), ":2\r\n");
+        assert_eq!(session.cmd(&["HSET", "h", "a", "10", "c", "3"]), ":1\r\n");
+        assert_eq!(session.cmd(&["HGET", "h", "a"]), "$2\r\n10\r\n");
+        assert_eq!(session.cmd(&["HGET", "h", "missing"]), "$-1\r\n");
+        assert_eq!(session.cmd(&["HGET", "missing", "a"]), "$-1\r\n");
+        assert_eq!(
+            session.cmd(&["HSET", "h", "odd"]),
+            "-ERR wrong number of arguments for 'hset' command\r\n"
+        );
+    }
+
+    #[test]
+    fn hgetall_returns_alternating_fields_and_values() {
+        let mut session = Session::new();
+        session.cmd(&["HSET", "h", "f", "v"]);
+
+        assert_eq!(
+            session.cmd(&["HGETALL", "h"]),
+            "*2\r\n$1\r\nf\r\n$1\r\nv\r\n"
+        );
+        assert_eq!(session.cmd(&["HGETALL", "missing"]), "*0\r\n");
+
+        session.cmd(&["HSET", "h", "g", "w"]);
+        let reply = session.cmd(&["HGETALL", "h"]);
+        let fields = "*4\r\n$1\r\nf\r\n$1\r\nv\r\n$1\r\ng\r\n$1\r\nw\r\n";
+        let swapped = "*4\r\n$1\r\ng\r\n$1\r\nw\r\n$1\r\nf\r\n$1\r\nv\r\n";
+        assert!(reply == fields || reply == swapped, "{}", reply);
+    }
+
+    #[test]
+    fn hdel_deletes_the_hash_once_empty() {
+        let mut session = Session::new();
+        session.cmd(&["HSET", "h", "a", "1", "b", "2"]);
+
+        assert_eq!(session.cmd(&["HDEL", "h", "a", "missing"]), ":1\r\n");
+        assert_eq!(session.cmd(&["EXISTS", "h"]), ":1\r\n");
+        assert_eq!(session.cmd(&["HDEL", "h", "b"]), ":1\r\n");
+        assert_eq!(session.cmd(&["EXISTS", "h"]), ":0\r\n");
+        assert_eq!(session.cmd(&["HDEL", "h", "b"]), ":0\r\n");
+    }
+
+    #[test]
+    fn hash_commands_against_a_string_are_wrongtype() {
+        let mut session = Session::new();
+        session.cmd(&["SET", "s", "v"]);
+        let wrongtype = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+
+        assert_eq!(session.cmd(&["HSET", "s", "f", "v"]), wrongtype);
+        assert_eq!(session.cmd(&["HGET", "s", "f"]), wrongtype);
+        assert_eq!(session.cmd(&["HGETALL", "s"]), wrongtype);
+        assert_eq!(session.cmd(&["HDEL", "s", "f"]), wrongtype);
+    }
+
+    fn info_section_headers(reply: &str) -> Vec<&str> {
+        reply
+            .split("\r\n")
+            .filter(|line| line.starts_with('#'))
+            .collect()
+    }
+
+    #[test]
+    fn info_returns_only_the_requested_sections() {
+        let mut session = Session::new();
+
+        let info = session.cmd(&["INFO", "server", "clients"]);
+        assert_eq!(info_section_headers(&info), ["# Server", "# Clients"]);
+        assert!(info.contains("\r\nconnected_clients:"), "{}", info);
+        assert!(!info.contains("role:"), "{}", info);
+    }
+
+    #[test]
+    fn info_defaults_to_every_section_in_order() {
+        let mut session = Session::new();
+
+        for args in [
+            &["INFO"][..],
+            &["INFO", "default"],
+            &["INFO", "keyspace", "all"],
+        ] {
+            let info = session.cmd(args);
+            assert_eq!(
+                info_section_headers(&info),
+                ["# Server", "# Clients", "# Replication", "# Keyspace"]
+            );
+        }
+    }
+
+    #[test]
+    fn hello_without_a_version_replies_with_the_server_map() {
+        let mut session = Session::new();
+        let id = session.resp.client_id;
+
+        assert_eq!(
+            session.cmd(&["HELLO"]),
+            format!(
+                "*14\r\n$6\r\nserver\r\n$5\r\nredis\r\n$7\r\nversion\r\n$5\r\n7.2.0\r\n\
+                 $5\r\nproto\r\n:2\r\n$2\r\nid\r\n:{}\r\n$4\r\nmode\r\n$10\r\nstandalone\r\n\
+                 $4\r\nrole\r\n$6\r\nmaster\r\n$7\r\nmodules\r\n*0\r\n",
+                id
+            )
+        );
+
+        session.cmd(&["REPLICAOF", "localhost", "6380"]);
+        let reply = session.cmd(&["HELLO", "2"]);
+        assert!(
+            reply.contains("$4\r\nrole\r\n$7\r\nreplica\r\n"),
+            "{}",
+            reply
+        );
+    }
+
+    #[test]
+    fn hello_refuses_protocol_versions_other_than_2() {
+        let mut session = Session::new();
+
+        assert_eq!(
+            session.cmd(&["HELLO", "4"]),
+            "-NOPROTO unsupported protocol version\r\n"
+        );
+        assert_eq!(
+            session.cmd(&["HELLO", "x"]),
+            "-ERR Protocol version is not an integer or out of range\r\n"
+        );
+    }
+
+    #[test]
+    fn replicaof_switches_the_reported_role() {
+        let mut session = Session::new();
+
+        assert_eq!(session.cmd(&["REPLICAOF", "localhost", "6380"]), "+OK\r\n");
+        let info = session.cmd(&["INFO", "replication"]);
+        assert!(info.contains("\r\nrole:slave\r\n"), "{}", info);
+
+        assert_eq!(session.cmd(&["REPLICAOF", "no", "one"]), "+OK\r\n");
+        let info = session.cmd(&["INFO", "replication"]);
+        assert!(info.contains("\r\nrole:master\r\n"), "{}", info);
+    }
+
+    #[test]
+    fn replicaof_rejects_an_invalid_port() {
+        let mut session = Session::new();
+
+        for port in ["abc", "-1", "65536"] {
+            assert_eq!(
+                session.cmd(&["REPLICAOF", "localhost", port]),
+                "-ERR Invalid master port\r\n"
+            );
+        }
+        let info = session.cmd(&["INFO", "replication"]);
+        assert!(info.contains("\r\nrole:master\r\n"), "{}", info);
+    }
+
+    #[test]
+    fn dangerous_debug_subcommands_are_refused_by_default() {
+        let mut session = Session::new();
+
+        for subcommand in ["PANIC", "segfault", "OOM"] {
+            assert_eq!(
+                session.cmd(&["DEBUG", subcommand]),
+                "-ERR DEBUG command not allowed\r\n"
+            );
+        }
+        assert_eq!(session.cmd(&["DEBUG", "JMAP"]), "+OK\r\n");
+    }
+
+    #[test]
+    fn unknown_debug_subcommand_is_a_syntax_error() {
+        let mut session = Session::new();
+
+        assert_eq!(
+            session.cmd(&["DEBUG", "fooBar"]),
+            "-ERR unknown subcommand or wrong number of arguments for 'fooBar'. Try DEBUG HELP.\r\n"
+        );
+        assert_eq!(
+            session.cmd(&["DEBUG", "sleep"]),
+            "-ERR unknown subcommand or wrong number of arguments for 'sleep'. Try DEBUG HELP.\r\n"
+        );
+    }
+
+    #[test]
+    fn unknown_command_echoes_the_name_as_sent() {
+        let mut session = Session::new();
+
+        assert_eq!(
+            session.cmd(&["fooBar", "a"]),
+            "-ERR unknown command 'fooBar', with args beginning with: 'a' \r\n"
+        );
+        let long_name = "x".repeat(200);
+        let reply = session.cmd(&[&long_name]);
+        assert!(
+            reply.contains(&format!("'{}'", "x".repeat(128))),
+            "{}",
+            reply
+        );
+    }
+
+    #[test]
+    fn client_input_echoed_in_an_error_cannot_inject_a_reply() {
+        let mut session = Session::new();
+        session.server_info.write().unwrap().enable_debug_command = true;
+
+        for input in [
+            vec!["foo\r\n+OK", "a\nb"],
+            vec!["DEBUG", "x\r\n:1"],
+            vec!["HELLO", "2", "a\r\nb"],
+        ] {
+            let reply = session.cmd(&input);
+            assert!(
+                reply.starts_with('-') && reply.ends_with("\r\n"),
+                "{:?}",
+                reply
+            );
+            assert!(
+                !reply[..reply.len() - 2].contains(['\r', '\n']),
+                "{:?}",
+                reply
+            );
+        }
+        assert_eq!(session.cmd(&["PING"]), "+PONG\r\n");
+    }
+
+    #[test]
+    fn format_float_drops_trailing_zeros_and_point() {
+        assert_eq!(format_float(3.0).unwrap(), "3");
+        assert_eq!(format_float(10.5).unwrap(), "10.5");
+        assert_eq!(format_float(-0.25).unwrap(), "-0.25");
+    }
+
+    #[test]
+    fn format_float_never_uses_an_exponent() {
+        assert_eq!(format_float(5.0e3).unwrap(), "5000");
+        assert_eq!(format_float(1e20).unwrap(), "100000000000000000000");
+        assert_eq!(format_float(1.5e-7).unwrap(), "0.00000015");
+    }
+
+    #[test]
+    fn format_float_keeps_f64_rounding_errors() {
+        assert_eq!(format_float(0.1 + 0.2).unwrap(), "0.30000000000000004");
+    }
+
+    #[test]
+    fn format_float_rejects_infinity_and_nan() {
+        for num in [f64::INFINITY, f64::NEG_INFINITY, f64::NAN] {
+            let err = format_float(num).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "ERR increment would produce NaN or Infinity"
+            );
+        }
     }
 }
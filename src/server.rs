@@ -0,0 +1,215 @@
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Error;
+use clap::{ArgAction, Parser};
+
+use crate::kv_store::KvStore;
+use crate::protocol::{DEFAULT_PROTO_MAX_BULK_LEN, ProtocolError, Request, Response, ServerInfo};
+
+#[derive(Parser, Debug)]
+pub struct Args {
+    #[arg(long, default_value = "6379")]
+    pub port: u16,
+    #[arg(long = "replicaof", default_value = None)]
+    pub replica_of: Option<String>,
+    /// Addresses to listen on, e.g. `--bind "127.0.0.1 -::1"`; a leading `-`
+    /// marks an address as optional.
+    #[arg(long, num_args = 1.., value_delimiter = ' ', default_values = ["127.0.0.1", "-::1"])]
+    pub bind: Vec<String>,
+    #[arg(long = "protected-mode", default_value = "yes", action = ArgAction::Set, value_parser = parse_yes_no)]
+    pub protected_mode: bool,
+    #[arg(long = "enable-debug-command", default_value = "no", action = ArgAction::Set, value_parser = parse_yes_no)]
+    pub enable_debug_command: bool,
+    /// Milliseconds between active expiration passes.
+    #[arg(long = "active-expire-interval", default_value = "100")]
+    pub active_expire_interval: u64,
+    /// Largest string, in bytes, a client may send or a command may build.
+    #[arg(long = "proto-max-bulk-len", default_value_t = DEFAULT_PROTO_MAX_BULK_LEN)]
+    pub proto_max_bulk_len: usize,
+}
+
+fn parse_yes_no(s: &str) -> Result<bool, String> {
+    match s.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(format!("argument must be 'yes' or 'no', got '{}'", s)),
+    }
+}
+
+const PROTECTED_MODE_ERROR: &str = "-DENIED Redis is running in protected mode because protected mode is enabled and no password is set for the default user. In this mode connections are only accepted from the loopback interface.\r\n";
+
+pub struct Server {
+    listeners: Vec<TcpListener>,
+    pub protected_mode: bool,
+    pub active_expire_interval: Duration,
+    info: Arc<RwLock<ServerInfo>>,
+    kv_store: Arc<RwLock<KvStore>>,
+    shutdown: AtomicBool,
+}
+
+impl Server {
+    pub fn new(info: ServerInfo, args: &Args) -> Result<Server, Error> {
+        let mut listeners = vec![];
+
+        for addr in &args.bind {
+            let (addr, optional) = match addr.strip_prefix('-') {
+                Some(addr) => (addr, true),
+                None => (addr.as_str(), false),
+            };
+            let ip = addr.parse::<IpAddr>()?;
+
+            match TcpListener::bind(SocketAddr::new(ip, info.port)) {
+                Ok(listener) => listeners.push(listener),
+                Err(e) if optional => {
+                    eprintln!("skipping optional bind address {}: {}", addr, e);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        if listeners.is_empty() {
+            return Err(Error::msg("no address to listen on"));
+        }
+
+        Ok(Server {
+            listeners,
+            protected_mode: args.protected_mode,
+            active_expire_interval: Duration::from_millis(args.active_expire_interval),
+            info: Arc::new(RwLock::new(info)),
+            kv_store: Arc::new(RwLock::new(KvStore::new())),
+            shutdown: AtomicBool::new(false),
+        })
+    }
+
+    // The first address listened on, with the port picked when it was 0.
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.listeners[0].local_addr()?)
+    }
+
+    // Makes `run` return once the connections already accepted are closed.
+    pub fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+
+        // Accepting doesn't time out, so connect once to each listener to
+        // get its loop to see the flag.
+        for listener in &self.listeners {
+            let Ok(mut addr) = listener.local_addr() else {
+                continue;
+            };
+            if addr.ip().is_unspecified() {
+                addr.set_ip(match addr {
+                    SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                    SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+                });
+            }
+            let _ = TcpStream::connect(addr);
+        }
+    }
+
+    fn is_shutting_down(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+
+    pub fn run(&self) {
+        thread::scope(|scope| {
+            for listener in &self.listeners {
+                scope.spawn(|| self.serve(listener));
+            }
+            scope.spawn(|| self.expire_keys());
+        });
+    }
+
+    // Reaps expired keys that are never read again. Each pass only looks at a
+    // bounded number of keys so the store lock is held briefly.
+    fn expire_keys(&self) {
+        while !self.is_shutting_down() {
+            thread::sleep(self.active_expire_interval);
+
+            self.kv_store.write().unwrap().sweep_expired();
+        }
+    }
+
+    fn serve(&self, listener: &TcpListener) {
+        let mut handles = vec![];
+
+        // Without a password, only loopback clients may use a wildcard listener.
+        let protected = self.protected_mode
+            && listener
+                .local_addr()
+                .map(|addr| addr.ip().is_unspecified())
+                .unwrap_or(false);
+
+        for stream in listener.incoming() {
+            if self.is_shutting_down() {
+                break;
+            }
+
+            match stream {
+                Ok(mut stream) => {
+                    if protected && !Server::is_loopback_peer(&stream) {
+                        let _ = stream.write_all(PROTECTED_MODE_ERROR.as_bytes());
+                        continue;
+                    }
+
+                    let kv_store = Arc::clone(&self.kv_store);
+                    let server_info = Arc::clone(&self.info);
+                    let handle = thread::spawn(move || {
+                        Server::handle_connection(stream, kv_store, server_info);
+                    });
+                    handles.push(handle);
+                }
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                }
+            }
+        }
+
+        for handle in handles {
+            let _ = handle.join();
+        }
+    }
+
+    fn is_loopback_peer(stream: &TcpStream) -> bool {
+        stream
+            .peer_addr()
+            .map(|addr| addr.ip().to_canonical().is_loopback())
+            .unwrap_or(false)
+    }
+
+    fn handle_connection(
+        stream: TcpStream,
+        kv_store: Arc<RwLock<KvStore>>,
+        server_info: Arc<RwLock<ServerInfo>>,
+    ) {
+        let max_bulk_len = server_info.read().unwrap().proto_max_bulk_len;
+        let mut req = Request::new(&stream, max_bulk_len);
+        let mut resp = Response::new(&stream);
+        server_info.write().unwrap().connected_clients += 1;
+
+        loop {
+            match req.read_command() {
+                Ok(true) => {
+                    if let Err(e) = resp.process_command(&req.command, &kv_store, &server_info) {
+                        eprintln!("error executing command: {}", e);
+                        break;
+                    }
+                }
+                Ok(false) => break,
+                Err(e) => {
+                    println!("error reading command: {}", e);
+                    if let Some(e) = e.downcast_ref::<ProtocolError>() {
+                        let _ = resp.send_protocol_error(e);
+                    }
+                    break;
+                }
+            }
+        }
+
+        server_info.write().unwrap().connected_clients -= 1;
+    }
+}
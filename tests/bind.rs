@@ -0,0 +1,46 @@
+mod common;
+
+use clap::Parser;
+use codecrafters_redis::protocol::{ServerInfo, ServerRole};
+use codecrafters_redis::server::{Args, Server};
+use common::TestServer;
+
+fn server_info() -> ServerInfo {
+    ServerInfo::new("0".repeat(40), 0, ServerRole::Master("master"))
+}
+
+#[test]
+fn wildcard_address_serves_loopback_clients() {
+    let server = TestServer::start_with_args(&["--bind", "0.0.0.0"], |_| {});
+    let mut client = server.client();
+
+    assert_eq!(client.send(&[b"PING"]), b"+PONG\r\n");
+}
+
+#[test]
+fn optional_address_that_cannot_be_bound_is_skipped() {
+    // 192.0.2.1 is reserved for documentation and never assigned locally.
+    let server = TestServer::start_with_args(&["--bind", "127.0.0.1 -192.0.2.1"], |_| {});
+    let mut client = server.client();
+
+    assert_eq!(client.send(&[b"PING"]), b"+PONG\r\n");
+}
+
+#[test]
+fn required_address_that_cannot_be_bound_is_an_error() {
+    let args = Args::parse_from([
+        "redis-server",
+        "--port",
+        "0",
+        "--bind",
+        "127.0.0.1 192.0.2.1",
+    ]);
+    assert!(Server::new(server_info(), &args).is_err());
+}
+
+#[test]
+fn only_optional_addresses_that_all_fail_is_an_error() {
+    let args = Args::parse_from(["redis-server", "--port", "0", "--bind=-192.0.2.1"]);
+    let err = Server::new(server_info(), &args).err().unwrap();
+    assert_eq!(err.to_string(), "no address to listen on");
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::TestServer;
+
+#[test]
+fn blpop_returns_an_element_pushed_after_a_delay() {
+    let server = TestServer::start();
+    let mut consumer = server.client();
+    let mut producer = server.client();
+
+    let start = Instant::now();
+    let blocked = thread::spawn(move || consumer.send(&[b"BLPOP", b"jobs", b"0"]));
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(producer.send(&[b"RPUSH", b"jobs", b"job1"]), b":1\r\n");
+
+    assert_eq!(
+        blocked.join().unwrap(),
+        b"*2\r\n$4\r\njobs\r\n$4\r\njob1\r\n"
+    );
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    assert_eq!(producer.send(&[b"LLEN", b"jobs"]), b":0\r\n");
+}
+
+#[test]
+fn brpop_times_out_with_a_null_array() {
+    let server = TestServer::start();
+    let mut client = server.client();
+
+    let start = Instant::now();
+    assert_eq!(client.send(&[b"BRPOP", b"jobs", b"0.05"]), b"*-1\r\n");
+    assert!(start.elapsed() >= Duration::from_millis(50));
+}
+
+#[test]
+fn negative_timeout_is_refused() {
+    let server = TestServer::start();
+    let mut client = server.client();
+
+    for timeout in [&b"-1"[..], b"-0.05"] {
+        assert_eq!(
+            client.send(&[b"BLPOP", b"jobs", timeout]),
+            b"-ERR timeout is negative\r\n"
+        );
+    }
+}
+
+#[test]
+fn huge_timeout_inside_multi_does_not_block_or_crash() {
+    let server = TestServer::start();
+    let mut client = server.client();
+
+    assert_eq!(client.send(&[b"MULTI"]), b"+OK\r\n");
+    assert_eq!(client.send(&[b"BLPOP", b"jobs", b"1e300"]), b"+QUEUED\r\n");
+    assert_eq!(client.send(&[b"EXEC"]), b"*1\r\n*-1\r\n");
+    assert_eq!(client.send(&[b"PING"]), b"+PONG\r\n");
+}
+
+#[test]
+fn a_disconnected_waiter_does_not_steal_the_element() {
+    let server = TestServer::start();
+    let mut gone = server.client();
+    let mut waiting = server.client();
+    let mut producer = server.client();
+
+    gone.send_raw_nowait(b"*3\r\n$5\r\nBLPOP\r\n$4\r\njobs\r\n$1\r\n0\r\n");
+    thread::sleep(Duration::from_millis(50));
+    let blocked = thread::spawn(move || waiting.send(&[b"BLPOP", b"jobs", b"0"]));
+    thread::sleep(Duration::from_millis(50));
+    drop(gone);
+    thread::sleep(Duration::from_millis(200));
+
+    assert_eq!(producer.send(&[b"RPUSH", b"jobs", b"job1"]), b":1\r\n");
+    assert_eq!(
+        blocked.join().unwrap(),
+        b"*2\r\n$4\r\njobs\r\n$4\r\njob1\r\n"
+    );
+}
@@ -0,0 +1,148 @@
+// Runs the real server on an ephemeral loopback port and talks RESP to it, so
+// commands can be exercised end to end over a socket.
+
+// Each test binary uses its own share of the helpers.
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use clap::Parser;
+use codecrafters_redis::protocol::{ServerInfo, ServerRole};
+use codecrafters_redis::server::{Args, Server};
+
+pub struct TestServer {
+    server: Arc<Server>,
+    addr: SocketAddr,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    pub fn start() -> TestServer {
+        TestServer::start_with(|_| {})
+    }
+
+    // Lets a test adjust the server settings before it starts listening.
+    pub fn start_with<F>(configure: F) -> TestServer
+    where
+        F: FnOnce(&mut ServerInfo),
+    {
+        TestServer::start_with_args(&["--bind", "127.0.0.1"], configure)
+    }
+
+    // Starts on an ephemeral port with extra command-line options, e.g. a
+    // different `--bind`.
+    pub fn start_with_args<F>(extra_args: &[&str], configure: F) -> TestServer
+    where
+        F: FnOnce(&mut ServerInfo),
+    {
+        let args = ["redis-server", "--port", "0"].iter().chain(extra_args);
+        let args = Args::parse_from(args);
+        let mut info = ServerInfo::new("0".repeat(40), 0, ServerRole::Master("master"));
+        configure(&mut info);
+
+        let server = Arc::new(Server::new(info, &args).expect("server should start"));
+        // A wildcard listener is reached through the loopback.
+        let mut addr = server.local_addr().unwrap();
+        if addr.ip().is_unspecified() {
+            addr.set_ip(Ipv4Addr::LOCALHOST.into());
+        }
+        let thread = {
+            let server = Arc::clone(&server);
+            thread::spawn(move || server.run())
+        };
+        TestServer {
+            server,
+            addr,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn client(&self) -> Client {
+        let stream = TcpStream::connect(self.addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            stream,
+        }
+    }
+}
+
+// Waits for the server to finish, which includes every client still open, so
+// clients have to be dropped before the server.
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.server.shutdown();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+pub struct Client {
+    reader: BufReader<TcpStream>,
+    stream: TcpStream,
+}
+
+impl Client {
+    // Sends one command as a RESP array and returns the raw reply.
+    pub fn send(&mut self, args: &[&[u8]]) -> Vec<u8> {
+        let mut request = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            request.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+            request.extend_from_slice(arg);
+            request.extend_from_slice(b"\r\n");
+        }
+        self.send_raw(&request)
+    }
+
+    // Sends bytes as they are, for pipelines or malformed input.
+    pub fn send_raw(&mut self, bytes: &[u8]) -> Vec<u8> {
+        self.send_raw_nowait(bytes);
+        self.read_reply()
+    }
+
+    // Sends bytes without waiting for a reply, for a command that blocks.
+    pub fn send_raw_nowait(&mut self, bytes: &[u8]) {
+        self.stream.write_all(bytes).unwrap();
+    }
+
+    // Reads one whole reply, nested arrays included.
+    pub fn read_reply(&mut self) -> Vec<u8> {
+        let mut reply = Vec::new();
+        read_reply_into(&mut self.reader, &mut reply);
+        reply
+    }
+
+    // True once the server closed the connection.
+    pub fn is_closed(&mut self) -> bool {
+        let mut byte = [0; 1];
+        matches!(self.reader.read(&mut byte), Ok(0))
+    }
+}
+
+fn read_reply_into(reader: &mut impl BufRead, reply: &mut Vec<u8>) {
+    let start = reply.len();
+    reader.read_until(b'\n', reply).unwrap();
+    let line = std::str::from_utf8(&reply[start..]).unwrap().trim_end();
+    let (kind, len) = line.split_at(1);
+
+    match (kind, len.parse::<i64>()) {
+        ("$", Ok(len)) if len >= 0 => {
+            let mut payload = vec![0; len as usize + 2];
+            reader.read_exact(&mut payload).unwrap();
+            reply.extend_from_slice(&payload);
+        }
+        ("*", Ok(cnt)) => {
+            for _ in 0..cnt.max(0) {
+                read_reply_into(reader, reply);
+            }
+        }
+        _ => {}
+    }
+}
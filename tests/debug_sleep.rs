@@ -0,0 +1,28 @@
+mod common;
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::TestServer;
+
+#[test]
+fn other_clients_are_served_while_one_sleeps() {
+    let server = TestServer::start();
+    let mut sleeper = server.client();
+    let mut other = server.client();
+
+    let start = Instant::now();
+    sleeper.send_raw_nowait(b"*3\r\n$5\r\nDEBUG\r\n$5\r\nSLEEP\r\n$3\r\n0.5\r\n");
+    thread::sleep(Duration::from_millis(50));
+
+    assert_eq!(other.send(&[b"SET", b"k", b"v"]), b"+OK\r\n");
+    assert_eq!(other.send(&[b"GET", b"k"]), b"$1\r\nv\r\n");
+    assert!(
+        start.elapsed() < Duration::from_millis(400),
+        "the other client waited for the sleep: {:?}",
+        start.elapsed()
+    );
+
+    assert_eq!(sleeper.read_reply(), b"+OK\r\n");
+    assert!(start.elapsed() >= Duration::from_millis(500));
+}
@@ -0,0 +1,21 @@
+mod common;
+
+use common::TestServer;
+
+#[test]
+fn echo_round_trips_crlf_and_nul() {
+    let server = TestServer::start();
+    let mut client = server.client();
+
+    let reply = client.send(&[b"ECHO", b"a\r\nb\0c"]);
+    assert_eq!(reply, b"$6\r\na\r\nb\0c\r\n");
+}
+
+#[test]
+fn get_returns_binary_value_set_earlier() {
+    let server = TestServer::start();
+    let mut client = server.client();
+
+    assert_eq!(client.send(&[b"SET", b"k\0", b"\r\n\0\xff"]), b"+OK\r\n");
+    assert_eq!(client.send(&[b"GET", b"k\0"]), b"$4\r\n\r\n\0\xff\r\n");
+}
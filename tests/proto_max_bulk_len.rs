@@ -0,0 +1,16 @@
+mod common;
+
+use common::TestServer;
+
+#[test]
+fn oversized_bulk_string_gets_a_protocol_error_and_disconnect() {
+    let server = TestServer::start_with(|info| info.proto_max_bulk_len = 16);
+    let mut client = server.client();
+
+    let reply = client.send_raw(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$17\r\n");
+    assert_eq!(reply, b"-ERR Protocol error: invalid bulk length\r\n");
+    assert!(client.is_closed());
+
+    let mut other = server.client();
+    assert_eq!(other.send(&[b"SET", b"k", &[b'x'; 16]]), b"+OK\r\n");
+}
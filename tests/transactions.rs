@@ -0,0 +1,75 @@
+mod common;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use common::TestServer;
+
+fn integers(reply: &[u8]) -> Vec<i64> {
+    let reply = std::str::from_utf8(reply).unwrap();
+    reply
+        .split("\r\n")
+        .filter_map(|line| line.strip_prefix(':'))
+        .map(|num| num.parse().unwrap())
+        .collect()
+}
+
+#[test]
+fn exec_applies_queued_increments_with_no_other_client_in_between() {
+    const QUEUED: usize = 100;
+    let server = TestServer::start();
+    let mut a = server.client();
+    let mut b = server.client();
+
+    let done = Arc::new(AtomicBool::new(false));
+    let interleaver = {
+        let done = Arc::clone(&done);
+        thread::spawn(move || {
+            let mut sent = 0;
+            while !done.load(Ordering::Relaxed) {
+                assert!(b.send(&[b"INCR", b"n"]).starts_with(b":"));
+                sent += 1;
+            }
+            sent
+        })
+    };
+
+    let mut applied = 0;
+    for _ in 0..20 {
+        assert_eq!(a.send(&[b"MULTI"]), b"+OK\r\n");
+        for _ in 0..QUEUED {
+            assert_eq!(a.send(&[b"INCR", b"n"]), b"+QUEUED\r\n");
+        }
+        let results = integers(&a.send(&[b"EXEC"]));
+
+        assert_eq!(results.len(), QUEUED);
+        // Consecutive values mean no increment from B landed inside EXEC.
+        for pair in results.windows(2) {
+            assert_eq!(pair[1], pair[0] + 1, "{:?}", results);
+        }
+        applied += QUEUED;
+    }
+
+    done.store(true, Ordering::Relaxed);
+    let sent = interleaver.join().unwrap();
+    assert!(sent > 0, "the other client never got to run");
+    let total = (applied + sent).to_string();
+    let expected = format!("${}\r\n{}\r\n", total.len(), total);
+    assert_eq!(a.send(&[b"GET", b"n"]), expected.as_bytes());
+}
+
+#[test]
+fn multi_on_one_connection_does_not_queue_another_connections_commands() {
+    let server = TestServer::start();
+    let mut a = server.client();
+    let mut b = server.client();
+
+    assert_eq!(a.send(&[b"MULTI"]), b"+OK\r\n");
+    assert_eq!(a.send(&[b"SET", b"k", b"from-a"]), b"+QUEUED\r\n");
+    assert_eq!(b.send(&[b"SET", b"k", b"from-b"]), b"+OK\r\n");
+    assert_eq!(b.send(&[b"GET", b"k"]), b"$6\r\nfrom-b\r\n");
+
+    assert_eq!(a.send(&[b"EXEC"]), b"*1\r\n+OK\r\n");
+    assert_eq!(b.send(&[b"GET", b"k"]), b"$6\r\nfrom-a\r\n");
+}